@@ -0,0 +1,148 @@
+//! Imports a synthetic yyets dump with different import options.
+//!
+//! Run with `cargo bench --bench import -- [records]`.
+
+use std::{collections::HashMap, env, fs::remove_file, path::PathBuf, time::Instant};
+
+use anyhow::Result;
+use rusqlite::params;
+use rubick_lib::{
+    database::{connect, migrate},
+    initialize::{import_series, CancellationToken, ImportOptions},
+    yyets::{Data, Info, Item, RecordData, YYeTsFile, YYeTsSeason, YYeTsSource},
+};
+
+const DEFAULT_RECORDS: i32 = 5000;
+
+fn record(id: i32) -> RecordData {
+    let formats = vec!["MP4".to_string(), "HR-HDTV".to_string()];
+    let mut items = HashMap::new();
+    for format in formats.iter() {
+        let series = (1..=12)
+            .map(|episode| Item {
+                itemid: format!("{}{}", id, episode),
+                episode: episode.to_string(),
+                name: format!("Show.{}.S01E{:02}.{}", id, episode, format),
+                size: "512MB".to_string(),
+                yyets_trans: episode % 2,
+                dateline: "1577836800".to_string(),
+                files: Some(vec![
+                    YYeTsFile {
+                        way: "1".to_string(),
+                        way_cn: "电驴".to_string(),
+                        address: format!("ed2k://|file|{}.{}.mp4|536870912|{:032}|/", id, episode, id),
+                        passwd: "".to_string(),
+                    },
+                    YYeTsFile {
+                        way: "2".to_string(),
+                        way_cn: "磁力".to_string(),
+                        address: format!("magnet:?xt=urn:btih:{:040}", id),
+                        passwd: "".to_string(),
+                    },
+                ]),
+            })
+            .collect();
+        items.insert(format.to_string(), series);
+    }
+    RecordData {
+        status: 1,
+        info: "OK".to_string(),
+        data: Data {
+            info: Info {
+                id,
+                cnname: format!("剧集{}", id),
+                enname: format!("Show {}", id),
+                aliasname: "".to_string(),
+                channel: "tv".to_string(),
+                channel_cn: "美剧".to_string(),
+                area: ["美国", "英国", "日本"][id as usize % 3].to_string(),
+                show_type: "".to_string(),
+                expire: "1577836800".to_string(),
+                views: id * 7,
+                year: vec![2020],
+            },
+            list: vec![YYeTsSeason {
+                season_num: "1".to_string(),
+                season_cn: "第1季".to_string(),
+                items,
+                formats,
+            }],
+        },
+    }
+}
+
+fn synthetic_dump(path: &PathBuf, records: i32) -> Result<()> {
+    let _ = remove_file(path);
+    let mut connection = connect(path)?;
+    connection.execute("CREATE TABLE yyets (id integer PRIMARY KEY, data text)", [])?;
+    let trx = connection.transaction()?;
+    for id in 1..=records {
+        trx.execute(
+            "insert into yyets (id, data) values (?1, ?2)",
+            params![id, serde_json::to_string(&record(id))?],
+        )?;
+    }
+    trx.commit()?;
+    Ok(())
+}
+
+fn bench(name: &str, source: &PathBuf, target: &PathBuf, options: ImportOptions) -> Result<()> {
+    let _ = remove_file(target);
+    let mut source = YYeTsSource::open(source)?;
+    let mut connection = connect(target)?;
+    migrate(&connection)?;
+    let started = Instant::now();
+    let summary = import_series(
+        &mut source,
+        &mut connection,
+        &CancellationToken::default(),
+        &options,
+        |_, _| {},
+    )?;
+    let elapsed = started.elapsed();
+    println!(
+        "{:<24} {:>8} records {:>10.2?} {:>10.0} records/s",
+        name,
+        summary.succeeded,
+        elapsed,
+        summary.succeeded as f64 / elapsed.as_secs_f64()
+    );
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let records = env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse::<i32>().ok())
+        .unwrap_or(DEFAULT_RECORDS);
+    let dir = env::temp_dir();
+    let source = dir.join("rubick-bench-yyets.db");
+    let target = dir.join("rubick-bench.db");
+    synthetic_dump(&source, records)?;
+
+    bench(
+        "single thread, default",
+        &source,
+        &target,
+        ImportOptions {
+            workers: 1,
+            bulk_load: false,
+            ..ImportOptions::default()
+        },
+    )?;
+    bench(
+        "single thread, bulk",
+        &source,
+        &target,
+        ImportOptions {
+            workers: 1,
+            bulk_load: true,
+            ..ImportOptions::default()
+        },
+    )?;
+    bench("parallel, bulk", &source, &target, ImportOptions::default())?;
+
+    let _ = remove_file(source);
+    let _ = remove_file(target);
+    Ok(())
+}
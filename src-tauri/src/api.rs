@@ -1,12 +1,15 @@
 use anyhow::Result;
 use rusqlite::{params, Connection};
 use serde::{Deserialize, Serialize};
-use tauri::command;
+use tauri::{async_runtime, command, AppHandle, Manager};
 
 use crate::{
     database::execute,
-    douban::{download_image, get_subject},
+    images, initialize,
+    links::{self, ParsedLink},
+    metadata,
     response::Response,
+    source,
 };
 
 #[derive(Serialize, Deserialize)]
@@ -25,6 +28,9 @@ pub struct SeriesFile {
     pub address: String,
     pub password: String,
     pub way: String,
+    pub way_code: String,
+    /// What `address` points to, parsed at import.
+    pub link: ParsedLink,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,6 +40,9 @@ pub struct Series {
     pub episode: i64,
     pub name: String,
     pub size: String,
+    pub upstream_id: String,
+    pub dateline: String,
+    pub yyets_trans: i64,
     pub files: Vec<SeriesFile>,
 }
 
@@ -45,7 +54,7 @@ pub struct Format {
     pub series: Vec<Series>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Season {
     pub id: i64,
     pub season: i64,
@@ -53,13 +62,16 @@ pub struct Season {
     pub formats: Vec<Format>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Resource {
     pub id: i64,
     pub name: String,
     pub original_name: String,
     pub alias_name: String,
     pub pic: String,
+    /// Thumbnails of `pic` for lists and for the detail page.
+    pub pic_list: String,
+    pub pic_detail: String,
     pub directors: String,
     pub writers: String,
     pub actors: String,
@@ -67,10 +79,33 @@ pub struct Resource {
     pub released_at: String,
     pub summary: String,
     pub rating: f64,
+    pub votes: i64,
+    /// Share of votes per star, from five stars down to one, in percent.
+    pub rating_distribution: Vec<f64>,
+    pub imdb_id: String,
+    pub episodes: i64,
+    /// In minutes, of an episode for series.
+    pub runtime: i64,
+    pub countries: String,
+    pub languages: String,
     pub seasons: Vec<Season>,
     pub channel: String,
+    pub channel_cn: String,
     pub area: String,
     pub favorite: bool,
+    pub upstream_id: i64,
+    pub show_type: String,
+    pub expire: String,
+    pub views: i64,
+    pub year: String,
+}
+
+impl Resource {
+    /// Points the thumbnail fields at the thumbnails of `pic`.
+    pub fn update_thumbnails(&mut self) {
+        self.pic_list = images::thumbnail(self.pic.as_str(), images::LIST);
+        self.pic_detail = images::thumbnail(self.pic.as_str(), images::DETAIL);
+    }
 }
 
 #[command]
@@ -95,73 +130,145 @@ pub async fn search(keyword: String) -> Response<Vec<SearchResult>> {
     }
 }
 
+/// Returns the stored resource at once. When its metadata is missing or stale
+/// it is enriched in the background, and the result is emitted as a
+/// `rubick_resource` event carrying the updated resource.
 #[command]
-pub async fn resource(id: i64) -> Response<Option<Resource>> {
+pub async fn resource(app_handle: AppHandle, id: i64) -> Response<Option<Resource>> {
     if id < 1 {
         return Response::fail("resource is not exists.", None);
     }
-    let result = execute(|db| {
-        let mut resource = db.query_row("select r.id, r.name, r.original_name, r.alias_name, r.pic, r.directors, r.writers, r.actors, r.types, r.released_at, r.summary, r.rating, c.name as channel, a.name as area from resources as r left join channels as c on c.id=r.channel_id left join areas as a on a.id=r.area_id where r.id=?", [id], |row| {
-            Ok(Resource {
-                id: row.get("id")?,
-                name: row.get("name")?,
-                original_name: row.get("original_name")?,
-                alias_name: row.get("alias_name")?,
-                pic: row.get("pic")?,
-                directors: row.get("directors")?,
-                writers: row.get("writers")?,
-                actors: row.get("actors")?,
-                types: row.get("types")?,
-                released_at: row.get("released_at")?,
-                summary: row.get("summary")?,
-                rating: row.get("rating")?,
-                channel: row.get("channel")?,
-                area: row.get("area")?,
-                favorite: false,
-                seasons: vec![],
-            })
-        })?;
-        resource.favorite = is_favorite(db, id);
-
-        resource.seasons = seasons_for_resource(db, id)?;
-        Ok(resource)
-    });
-    if let Ok(mut resource) = result {
-        if resource.pic.eq("") {
-            let result = get_subject(resource.name.as_str()).await;
-            if let Ok(subject) = result {
-                if let Ok(pic) = download_image(subject.pic.as_str()).await {
-                    resource.pic = pic;
-                }
-
-                resource.directors = subject.directors.to_string();
-                resource.writers = subject.writers.to_string();
-                resource.actors = subject.actors.to_string();
-                resource.types = subject.types.to_string();
-                resource.released_at = subject.released_at.to_string();
-                resource.summary = subject.summary.to_string();
-                resource.rating = subject.rating;
-                let _ = execute(|db| update_resource(db, &resource));
-            }
+    let result = execute(|db| load_resource(db, id));
+    if let Ok(resource) = result {
+        let _ = execute(|db| images::touch(db, resource.pic.as_str()));
+        if metadata::should_fetch(&resource) && metadata::start_enriching(id) {
+            let mut enriched = resource.clone();
+            async_runtime::spawn(async move {
+                let result = metadata::fetch(&mut enriched).await;
+                metadata::finish_enriching(id);
+                let response = match result {
+                    Ok(()) => Response::ok("success", Some(enriched)),
+                    Err(e) => Response::fail(e.to_string().as_str(), Some(enriched)),
+                };
+                let _ = app_handle.emit_all("rubick_resource", response);
+            });
         }
         return Response::ok("success", Some(resource));
     }
     Response::fail("resource is not exists.", None)
 }
 
-fn update_resource(db: &mut Connection, resource: &Resource) -> Result<usize> {
-    let size = db.execute("update resources set pic=?1, directors=?2, writers=?3, actors=?4, types=?5, released_at=?6, summary=?7, rating=?8 where id=?9", params![
-        resource.pic,
-        resource.directors,
-        resource.writers,
-        resource.actors,
-        resource.types,
-        resource.released_at,
-        resource.summary,
-        resource.rating,
-        resource.id
-    ])?;
-    Ok(size)
+/// Like `resource`, but waits for the metadata instead of enriching in the
+/// background. Used where no window listens for events, e.g. the CLI.
+pub async fn enriched_resource(id: i64) -> Response<Option<Resource>> {
+    match execute(|db| load_resource(db, id)) {
+        Ok(mut resource) => {
+            if metadata::should_fetch(&resource) {
+                let _ = metadata::fetch(&mut resource).await;
+            }
+            Response::ok("success", Some(resource))
+        }
+        Err(_) => Response::fail("resource is not exists.", None),
+    }
+}
+
+/// Loads a resource with its seasons, formats, series and files.
+pub fn load_resource(db: &mut Connection, id: i64) -> Result<Resource> {
+    let mut resource = db.query_row("select r.id, r.name, r.original_name, r.alias_name, r.pic, r.directors, r.writers, r.actors, r.types, r.released_at, r.summary, r.rating, r.votes, r.rating_distribution, r.imdb_id, r.episodes, r.runtime, r.countries, r.languages, r.upstream_id, r.show_type, r.expire, r.views, r.year, r.channel_cn, c.name as channel, a.name as area from resources as r left join channels as c on c.id=r.channel_id left join areas as a on a.id=r.area_id where r.id=?", [id], |row| {
+        Ok(Resource {
+            id: row.get("id")?,
+            name: row.get("name")?,
+            original_name: row.get("original_name")?,
+            alias_name: row.get("alias_name")?,
+            pic: row.get("pic")?,
+            pic_list: String::new(),
+            pic_detail: String::new(),
+            directors: row.get("directors")?,
+            writers: row.get("writers")?,
+            actors: row.get("actors")?,
+            types: row.get("types")?,
+            released_at: row.get("released_at")?,
+            summary: row.get("summary")?,
+            rating: row.get("rating")?,
+            votes: row.get("votes")?,
+            rating_distribution: serde_json::from_str(row.get::<_, String>("rating_distribution")?.as_str()).unwrap_or_default(),
+            imdb_id: row.get("imdb_id")?,
+            episodes: row.get("episodes")?,
+            runtime: row.get("runtime")?,
+            countries: row.get("countries")?,
+            languages: row.get("languages")?,
+            channel: row.get("channel")?,
+            channel_cn: row.get("channel_cn")?,
+            area: row.get("area")?,
+            favorite: false,
+            upstream_id: row.get("upstream_id")?,
+            show_type: row.get("show_type")?,
+            expire: row.get("expire")?,
+            views: row.get("views")?,
+            year: row.get("year")?,
+            seasons: vec![],
+        })
+    })?;
+    resource.favorite = is_favorite(db, id);
+    resource.update_thumbnails();
+
+    resource.seasons = seasons_for_resource(db, id)?;
+    Ok(resource)
+}
+
+impl From<Resource> for source::Resource {
+    fn from(resource: Resource) -> Self {
+        source::Resource {
+            upstream_id: resource.upstream_id,
+            name: resource.name,
+            original_name: resource.original_name,
+            alias_name: resource.alias_name,
+            channel: resource.channel,
+            channel_cn: resource.channel_cn,
+            area: resource.area,
+            show_type: resource.show_type,
+            expire: resource.expire,
+            views: resource.views,
+            year: resource.year,
+            seasons: resource
+                .seasons
+                .into_iter()
+                .map(|season| source::Season {
+                    season: season.season.to_string(),
+                    name: season.name,
+                    formats: season
+                        .formats
+                        .into_iter()
+                        .map(|format| source::Format {
+                            format: format.format,
+                            episodes: format
+                                .series
+                                .into_iter()
+                                .map(|series| source::Episode {
+                                    upstream_id: series.upstream_id,
+                                    episode: series.episode.to_string(),
+                                    name: series.name,
+                                    size: series.size,
+                                    dateline: series.dateline,
+                                    yyets_trans: series.yyets_trans,
+                                    links: series
+                                        .files
+                                        .into_iter()
+                                        .map(|file| source::Link {
+                                            way: file.way_code,
+                                            way_cn: file.way,
+                                            address: file.address,
+                                            password: file.password,
+                                        })
+                                        .collect(),
+                                })
+                                .collect(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
 }
 
 fn seasons_for_resource(db: &mut Connection, resource_id: i64) -> Result<Vec<Season>> {
@@ -258,6 +365,9 @@ fn series_for_formats(db: &mut Connection, format_ids: Vec<i64>) -> Result<Vec<S
                     episode: row.get("episode")?,
                     name: row.get("name")?,
                     size: row.get("size")?,
+                    upstream_id: row.get("upstream_id")?,
+                    dateline: row.get("dateline")?,
+                    yyets_trans: row.get("yyets_trans")?,
                     files: vec![],
                 })
             }
@@ -291,12 +401,32 @@ fn files_for_series(db: &Connection, series_ids: Vec<i64>) -> Result<Vec<SeriesF
             let mut files = vec![];
             while let Some(row) = rows.next()? {
                 let id = row.get("id")?;
+                let address: String = row.get("address")?;
+                let password: String = row.get("password")?;
+                let kind: String = row.get("link_kind")?;
+                // Catalogs imported before links were parsed are parsed on the fly.
+                let link = if kind.is_empty() {
+                    links::parse(address.as_str(), password.as_str())
+                } else {
+                    let trackers: String = row.get("trackers")?;
+                    ParsedLink {
+                        kind,
+                        name: row.get("file_name")?,
+                        size: row.get("file_size")?,
+                        hash: row.get("hash")?,
+                        trackers: trackers.lines().map(|tracker| tracker.to_string()).collect(),
+                        drive: row.get("drive")?,
+                        password: password.to_string(),
+                    }
+                };
                 files.push(SeriesFile {
                     id,
                     series_id: row.get("series_id")?,
-                    address: row.get("address")?,
-                    password: row.get("password")?,
+                    address,
+                    password,
                     way: row.get("way")?,
+                    way_code: row.get("way_code")?,
+                    link,
                 });
             }
             Ok(files)
@@ -311,6 +441,8 @@ pub struct Favorite {
     pub original_name: String,
     pub alias_name: String,
     pub pic: String,
+    pub pic_list: String,
+    pub pic_detail: String,
 }
 
 #[command]
@@ -320,12 +452,15 @@ pub async fn favorites() -> Response<Vec<Favorite>> {
         let mut rows = stmt.query([])?;
         let mut resources = vec![];
         while let Some(row) = rows.next()? {
+            let pic: String = row.get("pic")?;
             resources.push(Favorite {
                 id: row.get("id")?,
                 name: row.get("name")?,
                 original_name: row.get("original_name")?,
                 alias_name: row.get("alias_name")?,
-                pic: row.get("pic")?,
+                pic_list: images::thumbnail(pic.as_str(), images::LIST),
+                pic_detail: images::thumbnail(pic.as_str(), images::DETAIL),
+                pic,
             });
         }
         Ok(resources)
@@ -375,3 +510,72 @@ pub async fn un_favorite(resource_id: i64) -> Response<()> {
         Err(e) => Response::fail(e.to_string().as_str(), ()),
     }
 }
+
+/// Looks a resource up on Douban again, ignoring the TTL and backoff window.
+#[command]
+pub async fn refresh_metadata(id: i64) -> Response<Option<Resource>> {
+    let result = execute(|db| load_resource(db, id));
+    match result {
+        Ok(mut resource) => match metadata::fetch(&mut resource).await {
+            Ok(()) => Response::ok("success", Some(resource)),
+            Err(e) => Response::fail(e.to_string().as_str(), Some(resource)),
+        },
+        Err(e) => Response::fail(e.to_string().as_str(), None),
+    }
+}
+
+#[command]
+pub async fn link_metadata(id: i64, subject: String) -> Response<Option<Resource>> {
+    let result = execute(|db| load_resource(db, id));
+    match result {
+        Ok(mut resource) => match metadata::link(&mut resource, subject.as_str()).await {
+            Ok(()) => Response::ok("success", Some(resource)),
+            Err(e) => Response::fail(e.to_string().as_str(), Some(resource)),
+        },
+        Err(e) => Response::fail(e.to_string().as_str(), None),
+    }
+}
+
+#[command]
+pub async fn metadata_reviews() -> Response<Vec<metadata::MetadataReview>> {
+    match execute(metadata::reviews) {
+        Ok(reviews) => Response::ok("success", reviews),
+        Err(e) => Response::fail(e.to_string().as_str(), Vec::new()),
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ImportError {
+    pub id: i64,
+    pub upstream_id: i64,
+    pub stage: String,
+    pub message: String,
+    /// `initialize` or `links`.
+    pub report: String,
+}
+
+/// Records that failed to import, of the initialization unless `report` names
+/// another import such as `links`.
+#[command]
+pub async fn import_report(report: Option<String>) -> Response<Vec<ImportError>> {
+    let report = report.unwrap_or_else(|| initialize::INITIALIZE_REPORT.to_string());
+    let result = execute(|db| {
+        let mut stmt = db.prepare("select * from import_errors where report=?1 order by id asc")?;
+        let mut rows = stmt.query([report])?;
+        let mut errors = vec![];
+        while let Some(row) = rows.next()? {
+            errors.push(ImportError {
+                id: row.get("id")?,
+                upstream_id: row.get("upstream_id")?,
+                stage: row.get("stage")?,
+                message: row.get("message")?,
+                report: row.get("report")?,
+            });
+        }
+        Ok(errors)
+    });
+    match result {
+        Ok(errors) => Response::ok("success", errors),
+        Err(e) => Response::fail(e.to_string().as_str(), Vec::new()),
+    }
+}
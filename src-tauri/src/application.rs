@@ -2,7 +2,10 @@ use anyhow::Result;
 use std::{fs::create_dir_all, path::PathBuf};
 use tauri::App;
 
-use crate::database::{db, execute};
+use crate::{
+    database::{db, execute},
+    status::{self, InitializeStatus},
+};
 
 const IDENTIFIER: &str = "com.abtswath.rubick";
 
@@ -29,11 +32,30 @@ pub fn setup(app: &mut App) -> Result<()> {
     Ok(())
 }
 
+/// Creates the data directories without a running app, e.g. for the CLI.
+pub fn prepare() -> Result<()> {
+    for dir in [app_dir(), image_dir()] {
+        if !dir.exists() {
+            create_dir_all(dir)?;
+        }
+    }
+    Ok(())
+}
+
 pub fn initialized() -> bool {
     if !db().exists() {
         return false;
     }
 
+    match status::load() {
+        Ok(Some(state)) if state.status == InitializeStatus::Complete => return true,
+        Ok(_) => {}
+        Err(_) => return false,
+    }
+
+    // Catalogs imported before the state was persisted have no record, and a
+    // failed or interrupted re-import, e.g. from the CLI, leaves the live
+    // catalog in place while the state says otherwise.
     let result = execute(|connection| {
         let res = connection.query_row("select count(*) as c from resources", [], |row| {
             let size = row.get::<&str, i32>("c")?;
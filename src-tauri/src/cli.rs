@@ -0,0 +1,156 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::{
+    env,
+    fs::read_to_string,
+    io::{self, Write},
+    path::PathBuf,
+    thread,
+};
+
+use crate::{
+    api, application, database, douban,
+    initialize::{self, CancellationToken, Reporter},
+    response::Response,
+    source,
+};
+
+const USAGE: &str = "Usage: rubick [COMMAND]
+
+Starts the desktop app when no command is given.
+
+Commands:
+    init               Download and import the yyets database
+    import <zip>       Import a yyets_sqlite.zip that is already on disk
+    search <keyword>   Search resources by name
+    show <id>          Show a resource with its seasons and links
+    favorites          List favorite resources
+    export             Print every resource as JSON lines, as read by import_links
+    parse-douban <html>
+                       Parse a saved Douban subject page and list the fields
+                       that could not be found
+    help               Print this message";
+
+enum Command {
+    Init,
+    Import(PathBuf),
+    Search(String),
+    Show(i64),
+    Favorites,
+    Export,
+    ParseDouban(PathBuf),
+    Help,
+}
+
+/// Runs a subcommand without creating any window. Returns the exit code, or
+/// `None` when no subcommand was given and the GUI should start.
+pub fn run() -> Option<i32> {
+    let args: Vec<String> = env::args().collect();
+    let command = parse(&args)?;
+    attach_console();
+    let result = command.and_then(|command| {
+        thread::spawn(move || tauri::async_runtime::block_on(execute(command)))
+            .join()
+            .map_err(|_| anyhow!("Command panicked"))?
+    });
+    match result {
+        Ok(()) => Some(0),
+        Err(e) => {
+            eprintln!("{}", e);
+            Some(1)
+        }
+    }
+}
+
+/// Release builds on Windows use the windows subsystem and start without a
+/// console, so subcommands write to the one they were run from.
+#[cfg(windows)]
+fn attach_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    // Fails when there is no parent console or one is already attached.
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(windows))]
+fn attach_console() {}
+
+fn parse(args: &[String]) -> Option<Result<Command>> {
+    let name = args.get(1)?;
+    let argument = args.get(2).ok_or_else(|| anyhow!("Missing argument for `{}`\n\n{}", name, USAGE));
+    let command = match name.as_str() {
+        "init" => Ok(Command::Init),
+        "import" => argument.map(|archive| Command::Import(PathBuf::from(archive))),
+        "search" => argument.map(|keyword| Command::Search(keyword.to_string())),
+        "show" => argument.and_then(|id| {
+            id.parse::<i64>()
+                .map(Command::Show)
+                .map_err(|_| anyhow!("Invalid resource id `{}`", id))
+        }),
+        "favorites" => Ok(Command::Favorites),
+        "export" => Ok(Command::Export),
+        "parse-douban" => argument.map(|page| Command::ParseDouban(PathBuf::from(page))),
+        "help" | "-h" | "--help" => Ok(Command::Help),
+        _ => return None,
+    };
+    Some(command)
+}
+
+async fn execute(command: Command) -> Result<()> {
+    if let Command::Help = command {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    if let Command::ParseDouban(page) = command {
+        return print(&douban::parse_subject("", read_to_string(page)?));
+    }
+    application::prepare()?;
+    database::setup()?;
+    let token = CancellationToken::default();
+    match command {
+        Command::Init => print(&initialize::run(&Reporter::Console, &token).await?),
+        Command::Import(archive) => print(&initialize::import_archive(
+            &Reporter::Console,
+            &archive,
+            &token,
+        )?),
+        Command::Search(keyword) => respond(api::search(keyword).await),
+        Command::Show(id) => respond(api::enriched_resource(id).await),
+        Command::Favorites => respond(api::favorites().await),
+        Command::Export => export(),
+        Command::ParseDouban(_) | Command::Help => Ok(()),
+    }
+}
+
+fn respond<T: Serialize>(response: Response<T>) -> Result<()> {
+    if response.code != 0 {
+        return Err(anyhow!(response.message));
+    }
+    print(&response.data)
+}
+
+fn print<T: Serialize>(value: &T) -> Result<()> {
+    writeln!(io::stdout(), "{}", serde_json::to_string_pretty(value)?)?;
+    Ok(())
+}
+
+fn export() -> Result<()> {
+    database::execute(|db| {
+        let ids = db
+            .prepare("select id from resources order by id asc")?
+            .query_map([], |row| row.get::<usize, i64>(0))?
+            .collect::<Result<Vec<i64>, rusqlite::Error>>()?;
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        for id in ids {
+            let resource = source::Resource::from(api::load_resource(db, id)?);
+            writeln!(out, "{}", serde_json::to_string(&resource)?)?;
+        }
+        Ok(())
+    })
+}
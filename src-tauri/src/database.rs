@@ -1,10 +1,13 @@
 use anyhow::Result;
-use rusqlite::{Connection, Row};
-use std::path::PathBuf;
+use rusqlite::{Connection, Row, Transaction};
+use std::{
+    fs::remove_file,
+    path::{Path, PathBuf},
+};
 
-use crate::application::{app_dir};
+use crate::{application::app_dir, initialize::INITIALIZE_REPORT};
 
-const MIGRATIONS: [&'static str; 16] = [
+const MIGRATIONS: [&'static str; 25] = [
     "CREATE TABLE IF NOT EXISTS areas (
         id integer NOT NULL PRIMARY KEY AUTOINCREMENT,
         name text NOT NULL DEFAULT ''
@@ -83,6 +86,133 @@ const MIGRATIONS: [&'static str; 16] = [
     "CREATE INDEX IF NOT EXISTS format_id ON series (
         format_id ASC
     );",
+    "CREATE TABLE IF NOT EXISTS import_errors (
+        id integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+        upstream_id integer NOT NULL DEFAULT 0,
+        stage text NOT NULL DEFAULT '',
+        message text NOT NULL DEFAULT ''
+    );",
+    "CREATE TABLE IF NOT EXISTS metadata_fetches (
+        resource_id integer NOT NULL PRIMARY KEY,
+        subject_id text NOT NULL DEFAULT '',
+        status text NOT NULL DEFAULT '',
+        error text NOT NULL DEFAULT '',
+        attempts integer NOT NULL DEFAULT 0,
+        fetched_at integer NOT NULL DEFAULT 0
+    );",
+    "CREATE TABLE IF NOT EXISTS initialization (
+        id integer NOT NULL PRIMARY KEY,
+        status text NOT NULL DEFAULT 'not_started',
+        source text NOT NULL DEFAULT '',
+        dump text NOT NULL DEFAULT '',
+        error text NOT NULL DEFAULT '',
+        started_at integer NOT NULL DEFAULT 0,
+        updated_at integer NOT NULL DEFAULT 0
+    );",
+    "CREATE TABLE IF NOT EXISTS metadata_queue (
+        resource_id integer PRIMARY KEY,
+        status text NOT NULL DEFAULT 'pending',
+        queued_at integer NOT NULL DEFAULT 0
+    );",
+    "CREATE TABLE IF NOT EXISTS people (
+        id integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+        name text NOT NULL DEFAULT '',
+        douban_id text NOT NULL DEFAULT '',
+        UNIQUE (name, douban_id)
+    );",
+    "CREATE TABLE IF NOT EXISTS credits (
+        resource_id integer NOT NULL,
+        person_id integer NOT NULL,
+        role text NOT NULL,
+        position integer NOT NULL DEFAULT 0,
+        PRIMARY KEY (resource_id, person_id, role)
+    );",
+    "CREATE INDEX IF NOT EXISTS credits_person_id ON credits (person_id);",
+    "CREATE TABLE IF NOT EXISTS images (
+        filename text NOT NULL PRIMARY KEY,
+        size integer NOT NULL DEFAULT 0,
+        last_used_at integer NOT NULL DEFAULT 0
+    );",
+    "CREATE TABLE IF NOT EXISTS metadata_pins (
+        upstream_id integer NOT NULL PRIMARY KEY,
+        provider text NOT NULL DEFAULT 'douban',
+        subject_id text NOT NULL DEFAULT '',
+        pinned_at integer NOT NULL DEFAULT 0
+    );",
+];
+
+/// Columns added after the initial schema, as `(table, column, definition)`.
+const COLUMNS: [(&'static str, &'static str, &'static str); 29] = [
+    ("resources", "upstream_id", "integer NOT NULL DEFAULT 0"),
+    ("resources", "show_type", "text NOT NULL DEFAULT ''"),
+    ("resources", "expire", "text NOT NULL DEFAULT ''"),
+    ("resources", "views", "integer NOT NULL DEFAULT 0"),
+    ("resources", "year", "text NOT NULL DEFAULT ''"),
+    ("resources", "channel_cn", "text NOT NULL DEFAULT ''"),
+    ("series", "upstream_id", "text NOT NULL DEFAULT ''"),
+    ("series", "dateline", "text NOT NULL DEFAULT ''"),
+    ("series", "yyets_trans", "integer NOT NULL DEFAULT 0"),
+    ("files", "way_code", "text NOT NULL DEFAULT ''"),
+    ("metadata_fetches", "confidence", "real NOT NULL DEFAULT 0"),
+    ("metadata_fetches", "needs_review", "integer NOT NULL DEFAULT 0"),
+    ("metadata_fetches", "pinned", "integer NOT NULL DEFAULT 0"),
+    ("metadata_fetches", "provider", "text NOT NULL DEFAULT 'douban'"),
+    ("metadata_fetches", "missing", "text NOT NULL DEFAULT ''"),
+    ("resources", "votes", "integer NOT NULL DEFAULT 0"),
+    ("resources", "rating_distribution", "text NOT NULL DEFAULT '[]'"),
+    ("resources", "imdb_id", "text NOT NULL DEFAULT ''"),
+    ("resources", "episodes", "integer NOT NULL DEFAULT 0"),
+    ("resources", "runtime", "integer NOT NULL DEFAULT 0"),
+    ("resources", "countries", "text NOT NULL DEFAULT ''"),
+    ("resources", "languages", "text NOT NULL DEFAULT ''"),
+    ("files", "link_kind", "text NOT NULL DEFAULT ''"),
+    ("files", "file_name", "text NOT NULL DEFAULT ''"),
+    ("files", "file_size", "integer NOT NULL DEFAULT 0"),
+    ("files", "hash", "text NOT NULL DEFAULT ''"),
+    ("files", "trackers", "text NOT NULL DEFAULT ''"),
+    ("files", "drive", "text NOT NULL DEFAULT ''"),
+    ("import_errors", "report", "text NOT NULL DEFAULT 'initialize'"),
+];
+
+/// Tables rebuilt by an import. Everything else belongs to the user and is
+/// carried over by `swap_catalog`.
+pub const CATALOG_TABLES: [&'static str; 8] = [
+    "areas",
+    "channels",
+    "files",
+    "formats",
+    "resources",
+    "seasons",
+    "series",
+    "ways",
+];
+
+/// User tables referring to resources by id, moved to the new ids of their
+/// resources when a catalog is swapped in.
+const RESOURCE_TABLES: [&str; 4] = [
+    "favorites",
+    "metadata_fetches",
+    "metadata_queue",
+    "credits",
+];
+
+/// Columns of `resources` filled by metadata lookups rather than by imports.
+const METADATA_COLUMNS: [&str; 15] = [
+    "pic",
+    "directors",
+    "writers",
+    "actors",
+    "types",
+    "released_at",
+    "summary",
+    "rating",
+    "votes",
+    "rating_distribution",
+    "imdb_id",
+    "episodes",
+    "runtime",
+    "countries",
+    "languages",
 ];
 
 pub trait Model {
@@ -91,7 +221,7 @@ pub trait Model {
         Self: Sized;
 }
 
-pub fn connect(db: &PathBuf) -> Result<Connection> {
+pub fn connect(db: &Path) -> Result<Connection> {
     let connection = Connection::open(db)?;
     rusqlite::vtab::array::load_module(&connection)?;
     Ok(connection)
@@ -101,6 +231,33 @@ pub fn migrate(connection: &Connection) -> Result<()> {
     for migration in MIGRATIONS {
         connection.execute(migration, [])?;
     }
+    for (table, column, definition) in COLUMNS {
+        add_column(connection, table, column, definition)?;
+    }
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS resources_upstream_id ON resources (upstream_id)",
+        [],
+    )?;
+    // Pins used to be kept in `metadata_fetches`, which an import replaced.
+    connection.execute(
+        "insert or ignore into metadata_pins (upstream_id, provider, subject_id, pinned_at) select r.upstream_id, m.provider, m.subject_id, m.fetched_at from metadata_fetches as m inner join resources as r on r.id=m.resource_id where m.pinned=1 and m.subject_id<>'' and r.upstream_id<>0",
+        [],
+    )?;
+    Ok(())
+}
+
+fn add_column(connection: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let exists = connection.query_row(
+        "select count(*) from pragma_table_info(?1) where name=?2",
+        [table, column],
+        |row| row.get::<usize, i32>(0),
+    )?;
+    if exists <= 0 {
+        connection.execute(
+            format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition).as_str(),
+            [],
+        )?;
+    }
     Ok(())
 }
 
@@ -108,6 +265,127 @@ pub fn db() -> PathBuf {
     app_dir().join("rubick.db")
 }
 
+pub fn staging_db() -> PathBuf {
+    app_dir().join("rubick.staging.db")
+}
+
+pub fn remove_staging() {
+    let _ = remove_file(staging_db());
+}
+
+/// Replaces the catalog tables of the live database with those of `staging`
+/// in a single transaction. Favorites, looked up metadata and the other user
+/// data follow their resources to the new catalog.
+pub fn swap_catalog(staging: &Path) -> Result<()> {
+    execute(|connection| {
+        connection.execute(
+            "ATTACH DATABASE ?1 AS staging",
+            [staging.to_string_lossy().to_string()],
+        )?;
+        let result = copy_catalog(connection);
+        connection.execute("DETACH DATABASE staging", [])?;
+        result
+    })
+}
+
+fn copy_catalog(connection: &mut Connection) -> Result<()> {
+    let trx = connection.transaction()?;
+    // Only the resources something refers to need a new id.
+    trx.execute_batch(
+        "drop table if exists temp.old_resources;
+        create temp table old_resources as select * from main.resources where pic<>'' or id in (select resource_id from main.favorites union select resource_id from main.metadata_fetches union select resource_id from main.metadata_queue union select resource_id from main.credits);",
+    )?;
+    for table in CATALOG_TABLES {
+        copy_table(&trx, table, "1")?;
+    }
+    // Other reports, e.g. the one of `import_links`, are kept.
+    copy_table(
+        &trx,
+        "import_errors",
+        format!("report='{}'", INITIALIZE_REPORT).as_str(),
+    )?;
+
+    // Resources get new ids. They are matched by upstream id, or by name and
+    // year for catalogs imported before upstream ids were stored, where it
+    // is 0 on every row.
+    trx.execute_batch(
+        "drop table if exists temp.resource_ids;
+        create temp table resource_ids as select o.id as old_id, coalesce(
+            (select min(r.id) from main.resources as r where o.upstream_id<>0 and r.upstream_id=o.upstream_id),
+            (select min(r.id) from main.resources as r where o.upstream_id=0 and r.name=o.name and (o.year='' or r.year=o.year))
+        ) as new_id from temp.old_resources as o;
+        delete from temp.resource_ids where new_id is null;",
+    )?;
+    for table in RESOURCE_TABLES {
+        let columns = columns(&trx, "main", table)?;
+        let values: Vec<String> = columns
+            .iter()
+            .map(|column| match column.as_str() {
+                "resource_id" => "m.new_id".to_string(),
+                column => format!("t.{}", column),
+            })
+            .collect();
+        // Rows of resources missing from the new catalog are dropped, they
+        // would point at another resource or at nothing.
+        trx.execute_batch(
+            format!(
+                "drop table if exists temp.carried;
+                create temp table carried as select * from main.{table};
+                delete from main.{table};
+                insert or ignore into main.{table} ({columns}) select {values} from temp.carried as t inner join temp.resource_ids as m on m.old_id=t.resource_id;
+                drop table temp.carried;",
+                table = table,
+                columns = columns.join(", "),
+                values = values.join(", ")
+            )
+            .as_str(),
+        )?;
+    }
+    let assignments: Vec<String> = METADATA_COLUMNS
+        .iter()
+        .map(|column| format!("{column}=o.{column}", column = column))
+        .collect();
+    trx.execute_batch(
+        format!(
+            "update main.resources set {} from temp.resource_ids as m inner join temp.old_resources as o on o.id=m.old_id where m.new_id=resources.id;
+            drop table temp.resource_ids;
+            drop table temp.old_resources;",
+            assignments.join(", ")
+        )
+        .as_str(),
+    )?;
+    trx.commit()?;
+    Ok(())
+}
+
+/// Replaces the rows of `table` matching `condition` with those of staging.
+fn copy_table(trx: &Transaction, table: &str, condition: &str) -> Result<()> {
+    let columns = columns(trx, "staging", table)?.join(", ");
+    trx.execute(
+        format!("delete from main.{} where {}", table, condition).as_str(),
+        [],
+    )?;
+    trx.execute(
+        format!(
+            "insert into main.{table} ({columns}) select {columns} from staging.{table} where {condition}",
+            table = table,
+            columns = columns,
+            condition = condition
+        )
+        .as_str(),
+        [],
+    )?;
+    Ok(())
+}
+
+fn columns(trx: &Transaction, schema: &str, table: &str) -> Result<Vec<String>> {
+    let mut statement = trx.prepare("select name from pragma_table_info(?1, ?2)")?;
+    let columns = statement
+        .query_map([table, schema], |row| row.get::<usize, String>(0))?
+        .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+    Ok(columns)
+}
+
 pub fn setup() -> Result<()> {
     let db = db();
     let connection = connect(&db)?;
@@ -124,3 +402,141 @@ where
         Err(err) => Err(err),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use rusqlite::params;
+
+    use super::*;
+
+    fn open(name: &str) -> (PathBuf, Connection) {
+        let path = temp_dir().join(format!("rubick-test-{}-{}.db", name, std::process::id()));
+        let _ = remove_file(&path);
+        let connection = connect(&path).unwrap();
+        migrate(&connection).unwrap();
+        (path, connection)
+    }
+
+    fn insert_resources(db: &Connection, resources: &[(i64, &str, &str, i64)]) {
+        for (id, name, year, upstream_id) in resources {
+            db.execute(
+                "insert into resources (id, name, year, upstream_id) values (?1, ?2, ?3, ?4)",
+                params![id, name, year, upstream_id],
+            )
+            .unwrap();
+        }
+    }
+
+    fn swap(live: &mut Connection, staging: &Path) {
+        live.execute(
+            "ATTACH DATABASE ?1 AS staging",
+            [staging.to_string_lossy().to_string()],
+        )
+        .unwrap();
+        copy_catalog(live).unwrap();
+        live.execute("DETACH DATABASE staging", []).unwrap();
+    }
+
+    fn rows(db: &Connection, sql: &str) -> Vec<(i64, String)> {
+        let mut statement = db.prepare(sql).unwrap();
+        let rows = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        rows.collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn swap_carries_user_data_of_a_legacy_catalog() {
+        let (live_path, mut live) = open("legacy-live");
+        let (staging_path, staging) = open("legacy-staging");
+        // Imported before upstream ids were stored.
+        insert_resources(
+            &live,
+            &[
+                (1, "绝命毒师", "", 0),
+                (2, "风骚律师", "", 0),
+                (3, "黑镜", "", 0),
+                (4, "已下架", "", 0),
+            ],
+        );
+        live.execute_batch(
+            "insert into favorites (resource_id) values (2), (4), (1);
+            update resources set pic='p2.jpg', rating=8.9 where id=2;
+            insert into metadata_fetches (resource_id, subject_id, status) values (2, '26932282', 'ok');
+            insert into metadata_queue (resource_id) values (3);
+            insert into people (id, name) values (1, '鲍勃·奥登科克');
+            insert into credits (resource_id, person_id, role) values (2, 1, 'actor');
+            insert into import_errors (upstream_id, stage, message, report) values (1, 'parse', 'old', 'initialize'), (2, 'links', 'bad link', 'links');",
+        )
+        .unwrap();
+        insert_resources(
+            &staging,
+            &[
+                (1, "黑镜", "2011", 30),
+                (2, "风骚律师", "2015", 20),
+                (3, "绝命毒师", "2008", 10),
+            ],
+        );
+        staging
+            .execute(
+                "insert into import_errors (upstream_id, stage, message) values (5, 'parse', 'new')",
+                [],
+            )
+            .unwrap();
+        drop(staging);
+
+        swap(&mut live, &staging_path);
+        assert_eq!(
+            rows(&live, "select f.resource_id, r.name from favorites as f inner join resources as r on r.id=f.resource_id order by f.id"),
+            vec![(2, "风骚律师".to_string()), (3, "绝命毒师".to_string())]
+        );
+        assert_eq!(
+            rows(&live, "select resource_id, subject_id from metadata_fetches"),
+            vec![(2, "26932282".to_string())]
+        );
+        assert_eq!(
+            rows(&live, "select id, pic from resources where pic<>''"),
+            vec![(2, "p2.jpg".to_string())]
+        );
+        assert_eq!(
+            rows(&live, "select resource_id, status from metadata_queue"),
+            vec![(1, "pending".to_string())]
+        );
+        assert_eq!(
+            rows(&live, "select resource_id, role from credits"),
+            vec![(2, "actor".to_string())]
+        );
+        assert_eq!(
+            rows(&live, "select upstream_id, report from import_errors order by upstream_id"),
+            vec![(2, "links".to_string()), (5, "initialize".to_string())]
+        );
+        drop(live);
+        let _ = remove_file(live_path);
+        let _ = remove_file(staging_path);
+    }
+
+    #[test]
+    fn swap_matches_resources_by_upstream_id() {
+        let (live_path, mut live) = open("upstream-live");
+        let (staging_path, staging) = open("upstream-staging");
+        insert_resources(&live, &[(1, "旧名", "2008", 10), (2, "黑镜", "2011", 30)]);
+        live.execute("insert into favorites (resource_id) values (1), (2)", [])
+            .unwrap();
+        insert_resources(
+            &staging,
+            &[(1, "黑镜", "2011", 30), (2, "绝命毒师", "2008", 10)],
+        );
+        drop(staging);
+
+        swap(&mut live, &staging_path);
+        assert_eq!(
+            rows(&live, "select f.resource_id, r.name from favorites as f inner join resources as r on r.id=f.resource_id order by f.id"),
+            vec![(2, "绝命毒师".to_string()), (1, "黑镜".to_string())]
+        );
+        drop(live);
+        let _ = remove_file(live_path);
+        let _ = remove_file(staging_path);
+    }
+}
@@ -1,24 +1,42 @@
 use std::{
-    collections::hash_map::DefaultHasher,
+    collections::HashMap,
     error::Error,
     fmt::{self, Debug, Display, Formatter},
-    fs::File,
-    hash::Hasher,
-    io::Write,
+    time::Duration,
 };
 
 use anyhow::Result;
-use async_std::prelude::StreamExt;
-use reqwest::{Client, IntoUrl, Response, Url};
-use tauri::utils::html;
+use once_cell::sync::Lazy;
+use reqwest::{
+    header::{self, HeaderMap, HeaderValue},
+    IntoUrl, Response, Url,
+};
+use serde::Serialize;
+use tauri::utils::html::{self, NodeRef};
 
-use crate::application::image_dir;
+use crate::{
+    api::Resource,
+    http::{self, Scraper},
+    images,
+    matching::{best_match, Candidate, Query},
+    provider::{BoxFuture, Credit, Metadata, MetadataProvider, ACTOR, DIRECTOR, WRITER},
+    settings,
+    status::now,
+};
 
 const BASE_URL: &str = "https://www.douban.com";
 const SEARCH: &str = "/search";
 const DETAIL: &str = "/subject/";
 
-const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/103.0.0.0 Safari/537.36";
+/// Host Douban redirects to when it wants a captcha solved.
+const ANTI_BOT_HOST: &str = "sec.douban.com";
+/// Cookies of this domain and its subdomains are imported and sent.
+const COOKIE_DOMAIN: &str = "douban.com";
+/// How long to stop scraping after hitting the anti-bot check.
+const ANTI_BOT_COOLDOWN: Duration = Duration::from_secs(10 * 60);
+
+/// One request every two seconds, with a burst of three.
+static SCRAPER: Lazy<Scraper> = Lazy::new(|| Scraper::new(0.5, 3));
 
 struct DoubanError {
     error: Box<dyn Error + Send + Sync>,
@@ -50,142 +68,767 @@ impl Error for DoubanError {
     }
 }
 
+#[derive(Debug, Serialize)]
 pub struct Subject {
+    pub id: String,
+    /// How well the subject matched the resource, between 0 and 1.
+    pub confidence: f64,
     pub pic: String,
     pub directors: String,
     pub writers: String,
     pub actors: String,
+    /// Directors, writers and actors with their Douban person ids.
+    pub credits: Vec<Credit>,
     pub types: String,
     pub released_at: String,
     pub summary: String,
     pub rating: f64,
+    pub votes: i64,
+    /// Share of votes per star, from five stars down to one, in percent.
+    pub rating_distribution: Vec<f64>,
+    /// E.g. `tt0903747`.
+    pub imdb_id: String,
+    pub episodes: i64,
+    /// In minutes, of an episode for series.
+    pub runtime: i64,
+    pub countries: String,
+    pub languages: String,
+    /// Fields left empty because their nodes were not found on the page.
+    pub missing: Vec<&'static str>,
 }
 
-async fn get<U: IntoUrl>(client: &Client, url: U) -> Result<Response> {
-    let response = client
-        .get(url)
-        .header("User-Agent", DEFAULT_USER_AGENT)
-        .send()
-        .await?;
+async fn get<U: IntoUrl>(url: U) -> Result<Response> {
+    let url = url.into_url()?;
+    let mut headers = HeaderMap::new();
+    if is_douban_host(url.host_str().unwrap_or_default()) {
+        let cookie = settings::load().metadata.douban_cookie;
+        if let Ok(mut value) = HeaderValue::from_str(cookie.as_str()) {
+            if !value.is_empty() {
+                value.set_sensitive(true);
+                headers.insert(header::COOKIE, value);
+            }
+        }
+    }
+    let response = SCRAPER.get_with(url, headers).await?;
+    if response.url().host_str() == Some(ANTI_BOT_HOST) {
+        SCRAPER.block(ANTI_BOT_COOLDOWN);
+        return Err(anyhow::Error::from(DoubanError::new(
+            "Douban asked for a captcha, scraping is paused for a while",
+        )));
+    }
     Ok(response)
 }
 
-async fn get_subject_url(client: &Client, keyword: &str) -> Result<String> {
-    let response = get(
-        client,
-        format!("{}{}?cat=1002&q={}", BASE_URL, SEARCH, keyword),
-    )
-    .await?;
-    let contents = response.text().await?;
+fn is_douban_host(host: &str) -> bool {
+    let domain = host.trim_start_matches('.');
+    domain == COOKIE_DOMAIN || domain.ends_with(format!(".{}", COOKIE_DOMAIN).as_str())
+}
+
+/// Turns either a `Cookie` header value, as copied from the browser's dev
+/// tools, or a Netscape cookies.txt into the value sent to Douban. Cookies of
+/// other sites and expired ones are dropped. Returns the value and the number
+/// of cookies in it.
+pub fn parse_cookies(input: &str) -> Result<(String, usize)> {
+    let lines: Vec<&str> = input
+        .lines()
+        // Only strip line endings, a cookie's value may be empty.
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    let is_netscape = lines.iter().any(|line| {
+        (!line.starts_with('#') || line.starts_with("#HttpOnly_")) && line.split('\t').count() == 7
+    });
+    let mut cookies = vec![];
+    if is_netscape {
+        let now = now();
+        for line in lines {
+            // curl marks HttpOnly cookies by prefixing the domain.
+            let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+            let fields: Vec<&str> = line.split('\t').collect();
+            if line.starts_with('#') || fields.len() != 7 {
+                continue;
+            }
+            let expires = fields[4].parse::<i64>().unwrap_or(0);
+            if is_douban_host(fields[0]) && (expires == 0 || expires > now) {
+                cookies.push((fields[5].to_string(), fields[6].to_string()));
+            }
+        }
+    } else {
+        let input = input.trim();
+        let input = input
+            .strip_prefix("Cookie:")
+            .or_else(|| input.strip_prefix("cookie:"))
+            .unwrap_or(input);
+        for pair in input.split(';') {
+            if let Some((name, value)) = pair.split_once('=') {
+                cookies.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+    }
+    cookies.retain(|(name, _)| !name.is_empty());
+    if cookies.is_empty() {
+        return Err(anyhow::Error::from(DoubanError::new(
+            "No Douban cookie found",
+        )));
+    }
+    let value = cookies
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<String>>()
+        .join("; ");
+    // Never echo the cookies back, the error would end up in the UI and logs.
+    HeaderValue::from_str(value.as_str())
+        .map_err(|_| DoubanError::new("The cookies contain invalid characters"))?;
+    Ok((value, cookies.len()))
+}
+
+async fn search(keyword: &str) -> Result<Vec<Candidate>> {
+    let response = get(format!("{}{}?cat=1002&q={}", BASE_URL, SEARCH, keyword)).await?;
+    let contents = http::text(response).await?;
+    Ok(parse_candidates(contents))
+}
+
+pub fn parse_candidates(contents: String) -> Vec<Candidate> {
     let node = html::parse(contents);
+    let mut candidates = vec![];
+    let results = match node.select(".result-list .result") {
+        Ok(results) => results,
+        Err(_) => return candidates,
+    };
+    for result in results {
+        let result = result.as_node();
+        let link = match result.select_first(".content .title a") {
+            Ok(link) => link,
+            Err(_) => continue,
+        };
+        let id = match link
+            .attributes
+            .borrow()
+            .get("href")
+            .and_then(subject_id_from_link)
+        {
+            Some(id) => id,
+            None => continue,
+        };
+        let category = result
+            .select_first(".content .title h3>span")
+            .map(|el| el.text_contents())
+            .unwrap_or_default()
+            .trim_matches(|c: char| c == '[' || c == ']' || c.is_whitespace())
+            .to_string();
+        let cast = result
+            .select_first(".content .subject-cast")
+            .map(|el| el.text_contents())
+            .unwrap_or_default();
+        let original_title = cast
+            .split('/')
+            .next()
+            .and_then(|first| first.trim().strip_prefix("原名:"))
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        let year = cast
+            .rsplit('/')
+            .next()
+            .map(|last| last.trim())
+            .filter(|last| last.len() == 4)
+            .and_then(|last| last.parse::<i32>().ok());
+        candidates.push(Candidate {
+            id,
+            title: link.text_contents().trim().to_string(),
+            original_title,
+            year,
+            category,
+        });
+    }
+    candidates
+}
 
-    if let Ok(element) =
-        node.select_first(".result-list>div.result:nth-of-type(1) .content .title a")
-    {
-        if let Some(href) = element.attributes.borrow().get("href") {
-            let url = Url::parse(href)?;
-            let mut pairs = url.query_pairs();
-            let mut subject_url = String::from("");
-            while let Some((key, value)) = pairs.next() {
-                if key.eq("url") {
-                    let url = Url::parse(&value)?;
-                    if let Some(subject_id) = url.path().split("/").nth(2) {
-                        subject_url = format!("{}{}{}", BASE_URL, DETAIL, subject_id);
-                        break;
-                    }
+/// Extracts the subject id from a search result link, which wraps the
+/// subject url in its `url` query parameter.
+fn subject_id_from_link(href: &str) -> Option<String> {
+    let url = Url::parse(href).ok()?;
+    let target = url
+        .query_pairs()
+        .find(|(key, _)| key.eq("url"))
+        .map(|(_, value)| value.to_string())
+        .unwrap_or_else(|| href.to_string());
+    let target = Url::parse(&target).ok()?;
+    let id = target.path().split('/').nth(2)?;
+    if id.is_empty() {
+        return None;
+    }
+    Some(id.to_string())
+}
+
+pub async fn get_subject(query: &Query<'_>) -> Result<Subject> {
+    let candidates = search(query.name).await?;
+    let (candidate, confidence) = best_match(candidates, query)
+        .ok_or_else(|| anyhow::Error::from(DoubanError::new("Failed to get link of subject")))?;
+    let mut subject = get_detail(candidate.id.as_str()).await?;
+    subject.confidence = confidence;
+    Ok(subject)
+}
+
+/// Scrapes the subject with the given id, skipping the search.
+pub async fn get_subject_by_id(subject_id: &str) -> Result<Subject> {
+    get_detail(subject_id).await
+}
+
+/// Extracts the person id from a link such as `/celebrity/1027230/`.
+fn person_id_from_link(href: &str) -> Option<String> {
+    href.rsplit('/')
+        .find(|segment| !segment.is_empty())
+        .filter(|id| id.chars().all(|c| c.is_ascii_digit()))
+        .map(|id| id.to_string())
+}
+
+/// Accepts either a bare subject id or a subject url such as
+/// `https://movie.douban.com/subject/1234/`.
+pub fn parse_subject_id(input: &str) -> Result<String> {
+    let input = input.trim();
+    if !input.is_empty() && input.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(input.to_string());
+    }
+    Url::parse(input)
+        .ok()
+        .and_then(|url| {
+            let mut segments = url.path_segments()?;
+            match (segments.next(), segments.next()) {
+                (Some("subject"), Some(id)) if id.chars().all(|c| c.is_ascii_digit()) => {
+                    Some(id.to_string())
                 }
+                _ => None,
             }
-            return Ok(subject_url);
+        })
+        .filter(|id| !id.is_empty())
+        .ok_or_else(|| {
+            anyhow::Error::from(DoubanError::new(format!(
+                "Invalid Douban subject: {}",
+                input
+            )))
+        })
+}
+
+async fn get_detail(subject_id: &str) -> Result<Subject> {
+    let response = get(format!("{}{}{}", BASE_URL, DETAIL, subject_id)).await?;
+    let contents = http::text(response).await?;
+    Ok(parse_subject(subject_id, contents))
+}
+
+/// Text and links following a label of the `#info` block.
+#[derive(Default)]
+struct InfoField {
+    text: String,
+    /// `(text, href)` of every link, e.g. the people credited.
+    links: Vec<(String, String)>,
+}
+
+/// Reads the `#info` block by label, e.g. `导演` or `类型`. A label is a
+/// `span.pl` either wrapped with its value in a `span`, as for credits, or
+/// followed by its value up to the next `<br>`.
+fn info_fields(info: &NodeRef) -> HashMap<String, InfoField> {
+    let mut fields = HashMap::new();
+    let labels = match info.select("span.pl") {
+        Ok(labels) => labels,
+        Err(_) => return fields,
+    };
+    for label in labels {
+        let label = label.as_node();
+        let name = label
+            .text_contents()
+            .trim()
+            .trim_end_matches([':', '：'])
+            .trim()
+            .to_string();
+        let mut field = InfoField::default();
+        for sibling in label.following_siblings() {
+            if let Some(element) = sibling.as_element() {
+                let class = element
+                    .attributes
+                    .borrow()
+                    .get("class")
+                    .unwrap_or_default()
+                    .to_string();
+                if &*element.name.local == "br" || class.split(' ').any(|c| c == "pl") {
+                    break;
+                }
+                if &*element.name.local == "a" {
+                    field.links.push(link(&sibling));
+                } else if let Ok(links) = sibling.select("a") {
+                    field.links.extend(links.map(|a| link(a.as_node())));
+                }
+            }
+            field.text.push_str(sibling.text_contents().as_str());
         }
+        field.text = field
+            .text
+            .trim()
+            .trim_start_matches([':', '：'])
+            .trim()
+            .to_string();
+        fields.entry(name).or_insert(field);
     }
+    fields
+}
 
-    Err(anyhow::Error::from(DoubanError::new(
-        "Failed to get link of subject",
-    )))
+fn link(node: &NodeRef) -> (String, String) {
+    let href = node
+        .as_element()
+        .and_then(|element| {
+            element
+                .attributes
+                .borrow()
+                .get("href")
+                .map(|href| href.to_string())
+        })
+        .unwrap_or_default();
+    (node.text_contents().trim().to_string(), href)
 }
 
-pub async fn get_subject(resource_name: &str) -> Result<Subject> {
-    let client = Client::builder().build()?;
-    let subject_url = get_subject_url(&client, resource_name).await?;
-    let response = get(&client, subject_url).await?;
-    let contents = response.text().await?;
+/// Parses a subject page. Fields whose nodes could not be found are left
+/// empty and listed in `Subject::missing`.
+pub fn parse_subject(subject_id: &str, contents: String) -> Subject {
     let mut subject = Subject {
+        id: subject_id.to_string(),
+        confidence: 1.0,
         pic: String::from(""),
         directors: String::from(""),
         writers: String::from(""),
         actors: String::from(""),
+        credits: vec![],
         types: String::from(""),
         released_at: String::from(""),
         summary: String::from(""),
         rating: 0.0,
+        votes: 0,
+        rating_distribution: vec![],
+        imdb_id: String::new(),
+        episodes: 0,
+        runtime: 0,
+        countries: String::new(),
+        languages: String::new(),
+        missing: vec![],
     };
     let document = html::parse(contents);
-    if let Ok(element) = document.select_first("#content .article>div .subjectwrap") {
-        let node = element.as_node();
-        if let Ok(img) = node.select_first("#content .article>div .subjectwrap #mainpic>a>img") {
+    let text = |selector: &str| {
+        document
+            .select_first(selector)
+            .ok()
+            .map(|el| el.text_contents().trim().to_string())
+    };
+
+    match document.select_first("#mainpic img") {
+        Ok(img) => {
             if let Some(src) = img.attributes.borrow().get("src") {
                 subject.pic = src.to_string();
             }
         }
-        if let Ok(elements) = node.select(".subject #info>span:nth-child(1)>.attrs>a") {
-            let mut directors = vec![];
-            elements.for_each(|el| {
-                directors.push(el.text_contents());
-            });
-            subject.directors = directors.join("/");
-        }
-        if let Ok(elements) = node.select(".subject #info>span:nth-child(3)>.attrs>a") {
-            let mut writers = vec![];
-            elements.for_each(|el| {
-                writers.push(el.text_contents());
-            });
-            subject.writers = writers.join("/");
-        }
-        if let Ok(elements) = node.select(".subject #info>span:nth-child(5)>.attrs>a") {
-            let mut actors = vec![];
-            elements.for_each(|el| {
-                actors.push(el.text_contents());
-            });
-            subject.actors = actors.join("/");
+        Err(_) => subject.missing.push("pic"),
+    }
+
+    let fields = match document.select_first("#info") {
+        Ok(info) => info_fields(info.as_node()),
+        Err(_) => {
+            subject.missing.push("info");
+            HashMap::new()
         }
-        if let Ok(elements) = node.select(".subject #info>span[property='v:genre']") {
-            let mut types = vec![];
-            elements.for_each(|el| {
-                types.push(el.text_contents());
+    };
+    for (label, role, field) in [
+        ("导演", DIRECTOR, &mut subject.directors),
+        ("编剧", WRITER, &mut subject.writers),
+        ("主演", ACTOR, &mut subject.actors),
+    ] {
+        let links = fields
+            .get(label)
+            .map(|field| field.links.as_slice())
+            .unwrap_or_default();
+        let mut names = vec![];
+        for (name, href) in links {
+            subject.credits.push(Credit {
+                name: name.to_string(),
+                role: role.to_string(),
+                douban_id: person_id_from_link(href).unwrap_or_default(),
             });
-            subject.types = types.join("/");
+            names.push(name.to_string());
         }
-        if let Ok(el) = node.select_first(".subject #info>span[property='v:initialReleaseDate']") {
-            subject.released_at = el.text_contents();
+        *field = names.join("/");
+    }
+    if subject.directors.is_empty() && !fields.contains_key("导演") {
+        subject.missing.push("directors");
+    }
+    if subject.writers.is_empty() && !fields.contains_key("编剧") {
+        subject.missing.push("writers");
+    }
+    if subject.actors.is_empty() && !fields.contains_key("主演") {
+        subject.missing.push("actors");
+    }
+
+    subject.types = match document.select("#info span[property='v:genre']") {
+        Ok(genres) => genres
+            .map(|el| el.text_contents().trim().to_string())
+            .collect::<Vec<String>>()
+            .join("/"),
+        Err(_) => String::new(),
+    };
+    if subject.types.is_empty() {
+        subject.types = fields
+            .get("类型")
+            .map(|field| split_values(field.text.as_str()).join("/"))
+            .unwrap_or_default();
+    }
+    if subject.types.is_empty() {
+        subject.missing.push("types");
+    }
+
+    subject.released_at = text("#info span[property='v:initialReleaseDate']")
+        .or_else(|| {
+            ["首播", "上映日期"]
+                .iter()
+                .find_map(|label| fields.get(*label).map(|field| field.text.to_string()))
+        })
+        .unwrap_or_default();
+    if subject.released_at.is_empty() {
+        subject.missing.push("released_at");
+    }
+
+    match text("#interest_sectl .rating_num") {
+        // Subjects with too few votes have an empty rating.
+        Some(rating) => subject.rating = rating.parse::<f64>().unwrap_or(0.0),
+        None => subject.missing.push("rating"),
+    }
+    if subject.rating > 0.0 {
+        match text("#interest_sectl span[property='v:votes']") {
+            Some(votes) => subject.votes = votes.parse().unwrap_or(0),
+            None => subject.missing.push("votes"),
         }
-        if let Ok(el) = node.select_first("#interest_sectl .rating_wrap .rating_self .rating_num") {
-            if let Ok(rating) = el.text_contents().parse::<f64>() {
-                subject.rating = rating;
-            }
+        subject.rating_distribution = match document.select("#interest_sectl .rating_per") {
+            Ok(items) => items
+                .map(|el| {
+                    el.text_contents()
+                        .trim()
+                        .trim_end_matches('%')
+                        .parse()
+                        .unwrap_or(0.0)
+                })
+                .collect(),
+            Err(_) => vec![],
+        };
+        if subject.rating_distribution.is_empty() {
+            subject.missing.push("rating_distribution");
         }
     }
-    if let Ok(element) = document.select_first(".related-info>div>span:nth-child(1)") {
-        subject.summary = element.text_contents();
+
+    // Pages of subjects unknown to IMDb, or of movies, simply lack these.
+    subject.imdb_id = ["IMDb", "IMDb链接"]
+        .iter()
+        .filter_map(|label| fields.get(*label))
+        .find_map(|field| {
+            field
+                .text
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .find(|word| word.starts_with("tt"))
+                .map(|id| id.to_string())
+        })
+        .unwrap_or_default();
+    subject.episodes = fields
+        .get("集数")
+        .map(|field| leading_number(field.text.as_str()))
+        .unwrap_or(0);
+    subject.runtime = text("#info span[property='v:runtime']")
+        .or_else(|| {
+            ["单集片长", "片长"]
+                .iter()
+                .find_map(|label| fields.get(*label).map(|field| field.text.to_string()))
+        })
+        .map(|runtime| leading_number(runtime.as_str()))
+        .unwrap_or(0);
+    subject.countries = fields
+        .get("制片国家/地区")
+        .map(|field| split_values(field.text.as_str()).join("/"))
+        .unwrap_or_default();
+    subject.languages = fields
+        .get("语言")
+        .map(|field| split_values(field.text.as_str()).join("/"))
+        .unwrap_or_default();
+
+    match text("#link-report span.all.hidden")
+        .or_else(|| text("#link-report span[property='v:summary']"))
+    {
+        Some(summary) => subject.summary = summary,
+        None => subject.missing.push("summary"),
     }
-    Ok(subject)
+    subject
+}
+
+/// The number a value such as `45分钟` or `142分钟(导演剪辑版)` starts with.
+fn leading_number(text: &str) -> i64 {
+    text.trim()
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect::<String>()
+        .parse()
+        .unwrap_or(0)
+}
+
+/// Splits a `/`-separated value of the `#info` block.
+fn split_values(text: &str) -> Vec<String> {
+    text.split('/')
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect()
 }
 
 pub async fn download_image(src: &str) -> Result<String> {
-    let mut hasher = DefaultHasher::new();
-    hasher.write(src.as_bytes());
-    let mut filename = hasher.finish().to_string();
-    filename.push_str(".webp");
+    if let Some(filename) = images::cached(src) {
+        return Ok(filename);
+    }
+    images::save(src, get(src).await?).await
+}
 
-    let path = image_dir().join(&filename);
+pub struct DoubanProvider;
 
-    let client = Client::builder().build()?;
-    let response = get(&client, src).await?;
-    let mut stream = response.bytes_stream();
-    let mut file = File::create(path)?;
+impl From<Subject> for Metadata {
+    fn from(subject: Subject) -> Self {
+        Metadata {
+            subject_id: subject.id,
+            confidence: subject.confidence,
+            pic: subject.pic,
+            directors: subject.directors,
+            writers: subject.writers,
+            actors: subject.actors,
+            credits: subject.credits,
+            types: subject.types,
+            released_at: subject.released_at,
+            summary: subject.summary,
+            rating: subject.rating,
+            votes: subject.votes,
+            rating_distribution: subject.rating_distribution,
+            imdb_id: subject.imdb_id,
+            episodes: subject.episodes,
+            runtime: subject.runtime,
+            countries: subject.countries,
+            languages: subject.languages,
+            missing: subject
+                .missing
+                .iter()
+                .map(|field| field.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl MetadataProvider for DoubanProvider {
+    fn name(&self) -> &'static str {
+        "douban"
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        resource: &'a Resource,
+        pinned: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Option<Metadata>>> {
+        Box::pin(async move {
+            let subject = match pinned {
+                Some(subject_id) => get_subject_by_id(subject_id).await?,
+                None => get_subject(&Query::from(resource)).await?,
+            };
+            Ok(Some(Metadata::from(subject)))
+        })
+    }
+
+    fn image<'a>(&'a self, src: &'a str) -> BoxFuture<'a, Result<String>> {
+        Box::pin(download_image(src))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        read_to_string(format!(
+            "{}/tests/fixtures/douban/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn info_fields_reads_values_by_label() {
+        let document = html::parse(fixture("subject.html"));
+        let info = document.select_first("#info").unwrap();
+        let fields = info_fields(info.as_node());
+        let links = |label: &str| fields[label].links.clone();
+        assert_eq!(
+            links("导演"),
+            vec![
+                ("文斯·吉里根".to_string(), "/celebrity/1313148/".to_string()),
+                (
+                    "亚当·伯恩斯坦".to_string(),
+                    "/celebrity/1009494/".to_string()
+                ),
+            ]
+        );
+        assert_eq!(links("编剧").len(), 2);
+        assert_eq!(links("主演").len(), 3);
+        assert_eq!(fields["类型"].text, "剧情 / 惊悚 / 犯罪");
+        assert_eq!(fields["首播"].text, "2008-01-20(美国)");
+        assert_eq!(fields["集数"].text, "7");
+        assert_eq!(fields["单集片长"].text, "58分钟");
+        assert_eq!(fields["制片国家/地区"].text, "美国");
+        assert_eq!(fields["IMDb"].text, "tt0903747");
+        assert_eq!(
+            links("官方网站"),
+            vec![(
+                "www.amc.com/shows/breaking-bad".to_string(),
+                "http://www.amc.com/shows/breaking-bad".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn parse_subject_reads_a_full_page() {
+        let subject = parse_subject("2998002", fixture("subject.html"));
+        assert_eq!(
+            subject.pic,
+            "https://img9.doubanio.com/view/photo/s_ratio_poster/public/p2886584215.jpg"
+        );
+        assert_eq!(subject.directors, "文斯·吉里根/亚当·伯恩斯坦");
+        assert_eq!(subject.writers, "文斯·吉里根/彼得·古尔德");
+        assert_eq!(subject.actors, "布莱恩·克兰斯顿/亚伦·保尔/安娜·冈");
+        let credits: Vec<(&str, &str, &str)> = subject
+            .credits
+            .iter()
+            .map(|credit| {
+                (
+                    credit.role.as_str(),
+                    credit.name.as_str(),
+                    credit.douban_id.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(credits[0], (DIRECTOR, "文斯·吉里根", "1313148"));
+        assert_eq!(credits[3], (WRITER, "彼得·古尔德", "1317370"));
+        assert_eq!(credits[6], (ACTOR, "安娜·冈", "1049725"));
+        assert_eq!(credits.len(), 7);
+        assert_eq!(subject.types, "剧情/惊悚/犯罪");
+        assert_eq!(subject.released_at, "2008-01-20(美国)");
+        assert_eq!(subject.rating, 9.1);
+        assert_eq!(subject.votes, 170529);
+        assert_eq!(subject.rating_distribution, vec![69.6, 25.8, 4.1, 0.3, 0.2]);
+        assert_eq!(subject.imdb_id, "tt0903747");
+        assert_eq!(subject.episodes, 7);
+        assert_eq!(subject.runtime, 58);
+        assert_eq!(subject.countries, "美国");
+        assert_eq!(subject.languages, "英语/西班牙语");
+        assert!(subject.summary.ends_with("为家人留下一笔钱。"));
+        assert!(subject.missing.is_empty(), "{:?}", subject.missing);
+    }
+
+    #[test]
+    fn parse_subject_lists_missing_nodes() {
+        let subject = parse_subject("2998002", fixture("subject_stripped.html"));
+        assert_eq!(subject.missing, vec!["pic", "writers", "rating", "summary"]);
+        // Labels are the fallback when the RDFa attributes are gone.
+        assert_eq!(subject.types, "剧情/惊悚/犯罪");
+        assert_eq!(subject.released_at, "2008-01-20(美国)");
+        assert_eq!(subject.directors, "文斯·吉里根/亚当·伯恩斯坦");
+        assert_eq!(subject.writers, "");
+        assert_eq!(subject.imdb_id, "tt0903747");
+        assert_eq!(subject.episodes, 7);
+        assert_eq!(subject.runtime, 58);
+    }
+
+    #[test]
+    fn parse_candidates_reads_search_results() {
+        let candidates = parse_candidates(fixture("search.html"));
+        let candidates: Vec<(&str, &str, &str, Option<i32>, &str)> = candidates
+            .iter()
+            .map(|candidate| {
+                (
+                    candidate.id.as_str(),
+                    candidate.title.as_str(),
+                    candidate.original_title.as_str(),
+                    candidate.year,
+                    candidate.category.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            candidates,
+            vec![
+                (
+                    "2998002",
+                    "绝命毒师 第一季",
+                    "Breaking Bad Season 1",
+                    Some(2008),
+                    "电视剧"
+                ),
+                (
+                    "30211551",
+                    "续命之徒：绝命毒师电影",
+                    "El Camino: A Breaking Bad Movie",
+                    Some(2019),
+                    "电影"
+                ),
+                ("26932282", "风骚律师 第一季", "", None, "电视剧"),
+            ]
+        );
+    }
 
-    while let Some(item) = stream.next().await {
-        let chunk = item?;
-        file.write_all(&chunk)?;
+    #[test]
+    fn parse_cookies_reads_netscape_files() {
+        let (value, count) = parse_cookies(fixture("cookies.txt").as_str()).unwrap();
+        // Expired cookies and those of other sites are dropped.
+        assert_eq!(
+            value,
+            "bid=Xk2pQ9sLm1A; dbcl2=\"12345678:AbCdEfGhIjK\"; ll=\"108288\""
+        );
+        assert_eq!(count, 3);
+        // Windows line endings and an empty value survive.
+        let crlf = fixture("cookies.txt")
+            .replace('\n', "\r\n")
+            .replace("Xk2pQ9sLm1A", "");
+        assert_eq!(
+            parse_cookies(crlf.as_str()).unwrap().0.split("; ").next(),
+            Some("bid=")
+        );
+    }
+
+    #[test]
+    fn parse_cookies_reads_header_strings() {
+        let cases = [
+            "bid=Xk2pQ9sLm1A; dbcl2=\"12345678:AbCdEfGhIjK\"",
+            "Cookie: bid=Xk2pQ9sLm1A; dbcl2=\"12345678:AbCdEfGhIjK\";",
+            "  cookie:bid=Xk2pQ9sLm1A;dbcl2=\"12345678:AbCdEfGhIjK\"\n",
+        ];
+        for input in cases {
+            assert_eq!(
+                parse_cookies(input).unwrap(),
+                (
+                    "bid=Xk2pQ9sLm1A; dbcl2=\"12345678:AbCdEfGhIjK\"".to_string(),
+                    2
+                ),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn parse_cookies_rejects_inputs_without_douban_cookies() {
+        let other_sites = fixture("cookies.txt")
+            .lines()
+            .filter(|line| !line.contains(".douban.com"))
+            .collect::<Vec<&str>>()
+            .join("\n");
+        for input in ["", "   ", "Cookie:", "not a cookie", other_sites.as_str()] {
+            assert!(parse_cookies(input).is_err(), "{}", input);
+        }
+        assert!(parse_cookies("bid=a\u{7f}b").is_err());
     }
-    Ok(filename)
 }
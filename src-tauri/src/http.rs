@@ -0,0 +1,472 @@
+use std::{
+    error::Error,
+    fmt::{self, Debug, Display, Formatter},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use async_std::{future::timeout, task};
+use reqwest::{
+    header::{self, HeaderMap},
+    Client, ClientBuilder, IntoUrl, Proxy, Response, StatusCode, Url,
+};
+use serde::{Deserialize, Serialize};
+use tauri::command;
+
+use crate::{
+    response::Response as CommandResponse,
+    settings::{self, ProxySettings},
+};
+
+const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/103.0.0.0 Safari/537.36";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest wait for the response headers or for the next chunk of a body.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_RETRIES: u32 = 3;
+/// Delay before the first retry, doubled on every further one.
+const RETRY_BACKOFF: Duration = Duration::from_secs(2);
+/// Upper bound for a `Retry-After` sent by the server.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+/// Requested by `test_connection` when no url is given.
+const TEST_URL: &str = "https://movie.douban.com/";
+
+/// Bumped whenever the settings are saved, so clients built before pick the
+/// new proxy up.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+pub struct HttpError {
+    error: Box<dyn Error + Send + Sync>,
+}
+
+impl Debug for HttpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(self.error.to_string().as_str(), f)
+    }
+}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.error.to_string().as_str())
+    }
+}
+
+impl HttpError {
+    pub fn new<E: Into<Box<dyn Error + Send + Sync>>>(error: E) -> Self {
+        HttpError {
+            error: error.into(),
+        }
+    }
+}
+
+impl Error for HttpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// Token bucket allowing `burst` requests at once and refilling `rate` tokens
+/// per second.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        RateLimiter {
+            rate,
+            burst,
+            state: Mutex::new((burst, Instant::now())),
+        }
+    }
+
+    /// Waits until a token is available and takes it.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                let (tokens, last) = *state;
+                let now = Instant::now();
+                let tokens =
+                    (tokens + now.duration_since(last).as_secs_f64() * self.rate).min(self.burst);
+                if tokens >= 1.0 {
+                    *state = (tokens - 1.0, now);
+                    return;
+                }
+                *state = (tokens, now);
+                Duration::from_secs_f64((1.0 - tokens) / self.rate)
+            };
+            task::sleep(wait).await;
+        }
+    }
+}
+
+/// Makes clients built from now on read the settings again.
+pub fn reload() {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+fn proxy(settings: &ProxySettings) -> Result<Option<Proxy>> {
+    let target = match target(settings)? {
+        Some(target) => target,
+        None => return Ok(None),
+    };
+    let socks = is_socks(&target);
+    let bypass: Vec<String> = settings
+        .no_proxy
+        .split(',')
+        .map(|entry| entry.trim().to_ascii_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .collect();
+    let mut proxy = Proxy::custom(move |url| {
+        if bypasses(&bypass, url) {
+            None
+        } else {
+            Some(target.clone())
+        }
+    });
+    if !socks && !settings.username.is_empty() {
+        proxy = proxy.basic_auth(settings.username.as_str(), settings.password.as_str());
+    }
+    Ok(Some(proxy))
+}
+
+/// The url requests are proxied through. `basic_auth` only sets the
+/// `Proxy-Authorization` header of http proxies, so socks proxies get the
+/// credentials in the url instead, where reqwest picks them up.
+fn target(settings: &ProxySettings) -> Result<Option<Url>> {
+    let url = settings.url.trim();
+    if url.is_empty() {
+        return Ok(None);
+    }
+    // Fails early on urls reqwest cannot proxy through.
+    Proxy::all(url)?;
+    let mut target = Url::parse(url)?;
+    if is_socks(&target) && !settings.username.is_empty() {
+        let invalid = |_| HttpError::new("The proxy url cannot carry credentials");
+        target
+            .set_username(settings.username.as_str())
+            .map_err(invalid)?;
+        target
+            .set_password(Some(settings.password.as_str()))
+            .map_err(invalid)?;
+    }
+    Ok(Some(target))
+}
+
+fn is_socks(url: &Url) -> bool {
+    url.scheme().starts_with("socks")
+}
+
+/// Whether `url` is reached directly. Entries are `*`, a host or domain,
+/// matching its subdomains too, or an IP address or range such as
+/// `10.0.0.0/8`, as in the `NO_PROXY` environment variable.
+fn bypasses(entries: &[String], url: &Url) -> bool {
+    let host = url
+        .host_str()
+        .unwrap_or_default()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_ascii_lowercase();
+    let ip = host.parse::<IpAddr>().ok();
+    entries.iter().any(|entry| {
+        if entry == "*" {
+            return true;
+        }
+        if let (Some(ip), Some((network, prefix))) = (ip, entry.split_once('/')) {
+            return in_range(ip, network, prefix);
+        }
+        let domain = entry.trim_start_matches('.');
+        host == domain || host.ends_with(format!(".{}", domain).as_str())
+    })
+}
+
+fn in_range(ip: IpAddr, network: &str, prefix: &str) -> bool {
+    let (ip, network, bits) = match (ip, network.parse::<IpAddr>()) {
+        (IpAddr::V4(ip), Ok(IpAddr::V4(network))) => (
+            u128::from(u32::from(ip)),
+            u128::from(u32::from(network)),
+            32,
+        ),
+        (IpAddr::V6(ip), Ok(IpAddr::V6(network))) => (u128::from(ip), u128::from(network), 128),
+        _ => return false,
+    };
+    match prefix.parse::<u32>() {
+        Ok(0) => true,
+        Ok(prefix) if prefix <= bits => (ip ^ network) >> (bits - prefix) == 0,
+        _ => false,
+    }
+}
+
+fn builder_with(settings: &ProxySettings) -> Result<ClientBuilder> {
+    let builder = Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .user_agent(DEFAULT_USER_AGENT);
+    Ok(match proxy(settings)? {
+        Some(proxy) => builder.proxy(proxy),
+        None => builder,
+    })
+}
+
+/// A builder for clients going through the proxy of the settings. Every
+/// client the app creates should start from here.
+pub fn builder() -> Result<ClientBuilder> {
+    builder_with(&settings::load().proxy)
+}
+
+/// A client shared by every request to one site, throttled by a token bucket
+/// and retrying throttled or failed requests with exponential backoff.
+pub struct Scraper {
+    /// Rebuilt when the settings change, with the generation it was built at.
+    client: Mutex<(u64, Client)>,
+    limiter: RateLimiter,
+    /// Set while the site asks us to back off, e.g. after an anti-bot check.
+    blocked_until: Mutex<Option<Instant>>,
+}
+
+impl Scraper {
+    pub fn new(rate: f64, burst: u32) -> Self {
+        Scraper {
+            client: Mutex::new((GENERATION.load(Ordering::SeqCst), build())),
+            limiter: RateLimiter::new(rate, burst),
+            blocked_until: Mutex::new(None),
+        }
+    }
+
+    fn client(&self) -> Client {
+        let generation = GENERATION.load(Ordering::SeqCst);
+        let mut client = self.client.lock().unwrap();
+        if client.0 != generation {
+            *client = (generation, build());
+        }
+        client.1.clone()
+    }
+
+    /// Refuses every request for `duration`.
+    pub fn block(&self, duration: Duration) {
+        *self.blocked_until.lock().unwrap() = Some(Instant::now() + duration);
+    }
+
+    fn check_blocked(&self) -> Result<()> {
+        if let Some(until) = *self.blocked_until.lock().unwrap() {
+            let now = Instant::now();
+            if until > now {
+                return Err(anyhow::Error::from(HttpError::new(format!(
+                    "Blocked by the server, retry in {} seconds",
+                    (until - now).as_secs()
+                ))));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a GET request, retrying transport errors and 403, 429 or 5xx
+    /// responses. Other error statuses fail immediately. Errors leave the url
+    /// out, it may carry an api key and ends up in `metadata_fetches`.
+    pub async fn get<U: IntoUrl>(&self, url: U) -> Result<Response> {
+        self.get_with(url, HeaderMap::new()).await
+    }
+
+    /// Like `get`, sending `headers` along, e.g. a session cookie. Mark
+    /// secrets as sensitive so they never show up in debug output.
+    pub async fn get_with<U: IntoUrl>(&self, url: U, headers: HeaderMap) -> Result<Response> {
+        let url = url.into_url()?;
+        let mut attempt = 0;
+        loop {
+            self.check_blocked()?;
+            self.limiter.acquire().await;
+            let delay = match self.send(url.clone(), headers.clone()).await {
+                Ok(response) if !should_retry(response.status()) || attempt >= MAX_RETRIES => {
+                    return Ok(response
+                        .error_for_status()
+                        .map_err(reqwest::Error::without_url)?);
+                }
+                Err(e) if attempt >= MAX_RETRIES => return Err(e),
+                Ok(response) => retry_after(&response),
+                Err(_) => None,
+            };
+            task::sleep(delay.unwrap_or(RETRY_BACKOFF * 2_u32.pow(attempt))).await;
+            attempt += 1;
+        }
+    }
+
+    async fn send(&self, url: Url, headers: HeaderMap) -> Result<Response> {
+        let response = timeout(READ_TIMEOUT, self.client().get(url).headers(headers).send())
+            .await
+            .map_err(|_| {
+                HttpError::new(format!(
+                    "Timed out after {} seconds",
+                    READ_TIMEOUT.as_secs()
+                ))
+            })?
+            .map_err(reqwest::Error::without_url)?;
+        Ok(response)
+    }
+}
+
+/// Falls back to a direct client when the proxy settings are invalid, so the
+/// error shows up in `test_connection` rather than as a panic.
+fn build() -> Client {
+    builder()
+        .and_then(|builder| Ok(builder.build()?))
+        .unwrap_or_default()
+}
+
+/// Reads the whole body as text, failing if it stalls.
+pub async fn text(response: Response) -> Result<String> {
+    let text = timeout(READ_TIMEOUT, response.text())
+        .await
+        .map_err(|_| HttpError::new("Timed out while reading the response"))?
+        .map_err(reqwest::Error::without_url)?;
+    Ok(text)
+}
+
+fn should_retry(status: StatusCode) -> bool {
+    status == StatusCode::FORBIDDEN
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status.is_server_error()
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(|seconds| Duration::from_secs(seconds).min(MAX_RETRY_AFTER))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConnectionTest {
+    pub status: u16,
+    /// Milliseconds until the response headers arrived.
+    pub elapsed: u64,
+}
+
+/// Requests `url`, or a Douban page by default, through `proxy` so a proxy
+/// can be checked before it is saved.
+#[command]
+pub async fn test_connection(
+    proxy: ProxySettings,
+    url: Option<String>,
+) -> CommandResponse<Option<ConnectionTest>> {
+    let url = url
+        .filter(|url| !url.trim().is_empty())
+        .unwrap_or_else(|| TEST_URL.to_string());
+    let started = Instant::now();
+    let result = async {
+        let client = builder_with(&proxy)?.build()?;
+        let response = timeout(READ_TIMEOUT, client.get(url.as_str()).send())
+            .await
+            .map_err(|_| HttpError::new("Timed out"))??;
+        Ok::<_, anyhow::Error>(response.status())
+    }
+    .await;
+    match result {
+        Ok(status) => CommandResponse::ok(
+            "success",
+            Some(ConnectionTest {
+                status: status.as_u16(),
+                elapsed: started.elapsed().as_millis() as u64,
+            }),
+        ),
+        Err(e) => CommandResponse::fail(e.to_string().as_str(), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(url: &str, username: &str, password: &str) -> ProxySettings {
+        ProxySettings {
+            url: url.to_string(),
+            username: username.to_string(),
+            password: password.to_string(),
+            ..ProxySettings::default()
+        }
+    }
+
+    #[test]
+    fn target_carries_socks_credentials() {
+        let url = target(&settings("socks5://10.0.0.1:1080", "user", "p@ss"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(url.username(), "user");
+        assert_eq!(url.password(), Some("p%40ss"));
+        assert_eq!(url.host_str(), Some("10.0.0.1"));
+        assert_eq!(url.port(), Some(1080));
+
+        let url = target(&settings("socks5h://10.0.0.1:1080", "", ""))
+            .unwrap()
+            .unwrap();
+        assert_eq!((url.username(), url.password()), ("", None));
+    }
+
+    #[test]
+    fn target_leaves_http_credentials_to_basic_auth() {
+        let url = target(&settings("http://10.0.0.1:8080", "user", "pass"))
+            .unwrap()
+            .unwrap();
+        assert_eq!((url.username(), url.password()), ("", None));
+        assert!(target(&settings(" ", "user", "pass")).unwrap().is_none());
+        assert!(target(&settings("ftp://10.0.0.1", "", "")).is_err());
+    }
+
+    #[test]
+    fn bypasses_matches_hosts_domains_and_ranges() {
+        let entries: Vec<String> = ["localhost", ".douban.com", "10.0.0.0/8", "fd00::/8"]
+            .iter()
+            .map(|entry| entry.to_string())
+            .collect();
+        let cases = [
+            ("http://localhost:1420/", true),
+            ("https://douban.com/", true),
+            ("https://movie.douban.com/subject/1/", true),
+            ("https://notdouban.com/", false),
+            ("http://10.1.2.3/", true),
+            ("http://11.0.0.1/", false),
+            ("http://[fd12::1]/", true),
+            ("http://[fe80::1]/", false),
+            ("https://api.themoviedb.org/", false),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(
+                bypasses(&entries, &Url::parse(url).unwrap()),
+                expected,
+                "{}",
+                url
+            );
+        }
+        let all = vec!["*".to_string()];
+        assert!(bypasses(&all, &Url::parse("https://example.com/").unwrap()));
+        assert!(!bypasses(&[], &Url::parse("https://example.com/").unwrap()));
+    }
+
+    #[test]
+    fn in_range_checks_the_prefix() {
+        let ip: IpAddr = "192.168.1.20".parse().unwrap();
+        assert!(in_range(ip, "192.168.1.0", "24"));
+        assert!(in_range(ip, "192.168.1.20", "32"));
+        assert!(!in_range(ip, "192.168.2.0", "24"));
+        assert!(in_range(ip, "0.0.0.0", "0"));
+        // Invalid prefixes and mixed families never match.
+        assert!(!in_range(ip, "192.168.1.0", "33"));
+        assert!(!in_range(ip, "192.168.1.0", "x"));
+        assert!(!in_range(ip, "::", "0"));
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        assert!(in_range(ip, "2001:db8::", "32"));
+        assert!(!in_range(ip, "2001:db9::", "32"));
+    }
+}
@@ -0,0 +1,520 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    fs::{self, File},
+    hash::Hasher,
+    io::{Read, Write},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+use anyhow::Result;
+use async_std::{future::timeout, prelude::StreamExt};
+use image::{imageops::FilterType, ImageFormat};
+use reqwest::{header, Response};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use tauri::command;
+
+use crate::{
+    application::image_dir,
+    database::execute,
+    http::{HttpError, READ_TIMEOUT},
+    response::Response as CommandResponse,
+    settings,
+    status::now,
+};
+
+/// Extensions of the formats we recognise.
+const EXTENSIONS: [&str; 6] = ["jpg", "png", "gif", "webp", "bmp", "avif"];
+/// Thumbnail sizes as `(name, max width, max height)`, twice the size they
+/// are displayed at for high density screens.
+const THUMBNAILS: [(&str, u32, u32); 2] = [(LIST, 200, 420), (DETAIL, 320, 480)];
+pub const LIST: &str = "list";
+pub const DETAIL: &str = "detail";
+/// Suffix of downloads still in progress.
+const PARTIAL: &str = "part";
+/// Seconds a new image or download is spared by `collect_garbage`, as it is
+/// saved before the resource refers to it.
+const GRACE: i64 = 10 * 60;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CleanReport {
+    pub removed: u64,
+    /// Bytes freed.
+    pub freed: u64,
+    /// Bytes still used by the cache.
+    pub size: u64,
+}
+
+/// Name of the cached copy of `src`, without the extension.
+fn stem(src: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(src.as_bytes());
+    hasher.finish().to_string()
+}
+
+/// The hash part of a cached file name.
+fn stem_of(filename: &str) -> &str {
+    filename.split('.').next().unwrap_or_default()
+}
+
+fn thumbnail_name(filename: &str, size: &str) -> String {
+    format!("{}.{}.jpg", stem_of(filename), size)
+}
+
+fn is_thumbnail(filename: &str) -> bool {
+    THUMBNAILS
+        .iter()
+        .any(|(size, _, _)| filename.ends_with(format!(".{}.jpg", size).as_str()))
+}
+
+/// The `size` thumbnail of a cached image, or the image itself when the
+/// thumbnail could not be generated.
+pub fn thumbnail(filename: &str, size: &str) -> String {
+    if filename.is_empty() {
+        return String::new();
+    }
+    let thumbnail = thumbnail_name(filename, size);
+    if image_dir().join(&thumbnail).is_file() {
+        thumbnail
+    } else {
+        filename.to_string()
+    }
+}
+
+/// Writes the missing thumbnails of a cached image as JPEG next to it and
+/// returns their total size.
+fn create_thumbnails(dir: &Path, filename: &str) -> Result<u64> {
+    let mut size = 0;
+    let mut image = None;
+    for (name, width, height) in THUMBNAILS {
+        let path = dir.join(thumbnail_name(filename, name));
+        if !path.is_file() {
+            if image.is_none() {
+                image = Some(image::open(dir.join(filename))?);
+            }
+            if let Some(image) = image.as_ref() {
+                let thumbnail = if image.width() > width || image.height() > height {
+                    image.resize(width, height, FilterType::CatmullRom)
+                } else {
+                    image.clone()
+                };
+                thumbnail
+                    .to_rgb8()
+                    .save_with_format(&path, ImageFormat::Jpeg)?;
+            }
+        }
+        size += fs::metadata(&path)?.len();
+    }
+    Ok(size)
+}
+
+/// The file `src` was already saved as, if any.
+pub fn cached(src: &str) -> Option<String> {
+    let stem = stem(src);
+    EXTENSIONS
+        .iter()
+        .map(|extension| format!("{}.{}", stem, extension))
+        .find(|filename| image_dir().join(filename).is_file())
+}
+
+/// Guesses the format from the magic bytes, falling back on the content type.
+pub fn extension(head: &[u8], content_type: Option<&str>) -> Option<&'static str> {
+    let magic = if head.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("jpg")
+    } else if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+        Some("gif")
+    } else if head.len() >= 12 && head.starts_with(b"RIFF") && &head[8..12] == b"WEBP" {
+        Some("webp")
+    } else if head.starts_with(b"BM") {
+        Some("bmp")
+    } else if head.len() >= 12 && &head[4..8] == b"ftyp" && &head[8..12] == b"avif" {
+        Some("avif")
+    } else {
+        None
+    };
+    magic.or_else(|| match content_type?.split(';').next()?.trim() {
+        "image/jpeg" | "image/jpg" => Some("jpg"),
+        "image/png" => Some("png"),
+        "image/gif" => Some("gif"),
+        "image/webp" => Some("webp"),
+        "image/bmp" => Some("bmp"),
+        "image/avif" => Some("avif"),
+        _ => None,
+    })
+}
+
+/// Streams the image downloaded from `src` into the image dir and returns the
+/// file name it is stored under.
+pub async fn save(src: &str, response: Response) -> Result<String> {
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+    let stem = stem(src);
+    let partial = image_dir().join(format!("{}.{}", stem, PARTIAL));
+
+    let mut stream = response.bytes_stream();
+    let mut file = File::create(&partial)?;
+    let mut head = vec![];
+    let mut size = 0;
+    let result: Result<()> = async {
+        while let Some(item) = timeout(READ_TIMEOUT, stream.next())
+            .await
+            .map_err(|_| HttpError::new("Timed out while downloading the image"))?
+        {
+            let chunk = item?;
+            if head.len() < 16 {
+                head.extend_from_slice(&chunk[..chunk.len().min(16 - head.len())]);
+            }
+            size += chunk.len() as u64;
+            file.write_all(&chunk)?;
+        }
+        Ok(())
+    }
+    .await;
+    drop(file);
+    let extension = result.and_then(|_| {
+        extension(&head, content_type.as_deref())
+            .ok_or_else(|| anyhow::Error::from(HttpError::new("Not a supported image")))
+    });
+    let extension = match extension {
+        Ok(extension) => extension,
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+    };
+    let filename = format!("{}.{}", stem, extension);
+    fs::rename(&partial, image_dir().join(&filename))?;
+    stored(filename.as_str(), size)?;
+    Ok(filename)
+}
+
+/// Copies a local image into the image dir.
+pub fn copy(src: &Path) -> Result<String> {
+    let source = src.to_string_lossy().to_string();
+    if let Some(filename) = cached(source.as_str()) {
+        return Ok(filename);
+    }
+    let mut head = [0; 16];
+    let read = File::open(src)?.read(&mut head)?;
+    let extension = extension(&head[..read], None)
+        .ok_or_else(|| anyhow::Error::from(HttpError::new("Not a supported image")))?;
+    let filename = format!("{}.{}", stem(source.as_str()), extension);
+    let size = fs::copy(src, image_dir().join(&filename))?;
+    stored(filename.as_str(), size)?;
+    Ok(filename)
+}
+
+fn stored(filename: &str, size: u64) -> Result<()> {
+    let dir = image_dir();
+    let size = size + create_thumbnails(&dir, filename).unwrap_or(0);
+    execute(|db| {
+        db.execute(
+            "insert or replace into images (filename, size, last_used_at) values (?1, ?2, ?3)",
+            params![filename, size, now()],
+        )?;
+        evict(
+            db,
+            &dir,
+            settings::load().image_cache_size * 1024 * 1024,
+            filename,
+        )?;
+        Ok(())
+    })
+}
+
+/// Marks an image as just used, for the LRU eviction.
+pub fn touch(db: &Connection, filename: &str) -> Result<()> {
+    if !filename.is_empty() {
+        db.execute(
+            "update images set last_used_at=?1 where filename=?2",
+            params![now(), filename],
+        )?;
+    }
+    Ok(())
+}
+
+fn remove(db: &Connection, dir: &Path, filename: &str) -> Result<()> {
+    let paths = THUMBNAILS
+        .iter()
+        .map(|(size, _, _)| dir.join(thumbnail_name(filename, size)))
+        .chain([dir.join(filename)]);
+    for path in paths {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    db.execute("delete from images where filename=?1", [filename])?;
+    Ok(())
+}
+
+/// Removes the least recently used images until the cache fits in `cap`
+/// bytes, keeping those of favorites. Resources losing their poster are
+/// looked up again on their next view. A `cap` of 0 disables the limit.
+pub fn evict(db: &Connection, dir: &Path, cap: u64, keep: &str) -> Result<CleanReport> {
+    let mut report = CleanReport {
+        size: db.query_row("select coalesce(sum(size), 0) from images", [], |row| {
+            row.get(0)
+        })?,
+        ..Default::default()
+    };
+    if cap == 0 || report.size <= cap {
+        return Ok(report);
+    }
+    let candidates = {
+        let mut stmt = db.prepare("select filename, size from images where filename not in (select r.pic from favorites as f inner join resources as r on r.id=f.resource_id) and filename!=?1 order by last_used_at asc")?;
+        let rows = stmt.query_map([keep], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<rusqlite::Result<Vec<(String, u64)>>>()?
+    };
+    for (filename, size) in candidates {
+        if report.size <= cap {
+            break;
+        }
+        db.execute(
+            "update metadata_fetches set fetched_at=0 where resource_id in (select id from resources where pic=?1)",
+            [filename.as_str()],
+        )?;
+        db.execute(
+            "update resources set pic='' where pic=?1",
+            [filename.as_str()],
+        )?;
+        remove(db, dir, filename.as_str())?;
+        report.removed += 1;
+        report.freed += size;
+        report.size -= size;
+    }
+    Ok(report)
+}
+
+/// Deletes images no resource references and leftovers of interrupted
+/// downloads, and tracks files cached before the `images` table existed.
+/// Images and downloads younger than `GRACE` are kept.
+pub fn collect_garbage(db: &Connection, dir: &Path) -> Result<CleanReport> {
+    let referenced = {
+        let mut stmt = db.prepare("select distinct pic from resources where pic!=''")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect::<rusqlite::Result<HashSet<String>>>()?
+    };
+    let recent = {
+        let mut stmt = db.prepare("select filename from images where last_used_at>=?1")?;
+        let rows = stmt.query_map([now() - GRACE], |row| row.get(0))?;
+        rows.collect::<rusqlite::Result<HashSet<String>>>()?
+    };
+    let stems: HashSet<&str> = referenced
+        .iter()
+        .chain(recent.iter())
+        .map(|pic| stem_of(pic))
+        .collect();
+    let mut report = CleanReport::default();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let filename = entry.file_name().to_string_lossy().to_string();
+        if is_thumbnail(filename.as_str()) && stems.contains(stem_of(filename.as_str())) {
+            continue;
+        }
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs() as i64)
+            .unwrap_or(0);
+        if !referenced.contains(&filename) {
+            let partial = filename.ends_with(format!(".{}", PARTIAL).as_str());
+            if recent.contains(&filename) || (partial && modified >= now() - GRACE) {
+                continue;
+            }
+            fs::remove_file(entry.path())?;
+            db.execute("delete from images where filename=?1", [filename.as_str()])?;
+            report.removed += 1;
+            report.freed += metadata.len();
+            continue;
+        }
+        let size = metadata.len() + create_thumbnails(dir, filename.as_str()).unwrap_or(0);
+        db.execute(
+            "insert into images (filename, size, last_used_at) values (?1, ?2, ?3) on conflict (filename) do update set size=excluded.size",
+            params![filename, size, modified],
+        )?;
+    }
+    let missing = {
+        let mut stmt = db.prepare("select filename from images")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect::<rusqlite::Result<Vec<String>>>()?
+            .into_iter()
+            .filter(|filename| !dir.join(filename).exists())
+            .collect::<Vec<String>>()
+    };
+    for filename in missing {
+        remove(db, dir, filename.as_str())?;
+    }
+    report.size = db.query_row("select coalesce(sum(size), 0) from images", [], |row| {
+        row.get(0)
+    })?;
+    Ok(report)
+}
+
+/// Runs the garbage collector, then enforces the configured size cap.
+#[command]
+pub async fn clean_images() -> CommandResponse<CleanReport> {
+    let result = execute(|db| {
+        let dir = image_dir();
+        let garbage = collect_garbage(db, &dir)?;
+        let evicted = evict(
+            db,
+            &dir,
+            settings::load().image_cache_size * 1024 * 1024,
+            "",
+        )?;
+        Ok(CleanReport {
+            removed: garbage.removed + evicted.removed,
+            freed: garbage.freed + evicted.freed,
+            size: evicted.size,
+        })
+    });
+    match result {
+        Ok(report) => CommandResponse::ok("success", report),
+        Err(e) => CommandResponse::fail(e.to_string().as_str(), CleanReport::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, path::PathBuf};
+
+    use crate::database::migrate;
+
+    use super::*;
+
+    fn setup(name: &str) -> (PathBuf, Connection) {
+        let dir = temp_dir().join(format!("rubick-images-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let db = Connection::open_in_memory().unwrap();
+        migrate(&db).unwrap();
+        (dir, db)
+    }
+
+    fn write(dir: &Path, filename: &str, size: usize) {
+        fs::write(dir.join(filename), vec![0; size]).unwrap();
+    }
+
+    fn filenames(dir: &Path) -> Vec<String> {
+        let mut filenames: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        filenames.sort();
+        filenames
+    }
+
+    #[test]
+    fn extension_reads_magic_bytes() {
+        let cases: [(&[u8], Option<&str>, Option<&str>); 9] = [
+            (&[0xFF, 0xD8, 0xFF, 0xE0], None, Some("jpg")),
+            (b"\x89PNG\r\n\x1a\n\0\0", None, Some("png")),
+            (b"GIF89a\x01\0", None, Some("gif")),
+            (b"RIFF\x24\0\0\0WEBPVP8 ", None, Some("webp")),
+            (b"BM\x36\0", None, Some("bmp")),
+            (b"\0\0\0\x1cftypavif", None, Some("avif")),
+            // The magic bytes win over a wrong content type.
+            (b"\x89PNG\r\n\x1a\n", Some("image/jpeg"), Some("png")),
+            (b"<html>", Some("image/webp; charset=binary"), Some("webp")),
+            (b"<html>", Some("text/html"), None),
+        ];
+        for (head, content_type, expected) in cases {
+            assert_eq!(extension(head, content_type), expected, "{:?}", head);
+        }
+    }
+
+    #[test]
+    fn collect_garbage_spares_fresh_files() {
+        let (dir, db) = setup("garbage");
+        let stale = now() - GRACE - 60;
+        for (filename, size) in [
+            ("1.jpg", 10),
+            ("1.list.jpg", 5),
+            ("2.jpg", 20),
+            ("2.list.jpg", 5),
+            ("3.png", 30),
+            ("3.detail.jpg", 5),
+            ("4.gif", 40),
+            ("5.part", 50),
+        ] {
+            write(&dir, filename, size);
+        }
+        db.execute_batch(&format!(
+            "insert into resources (pic) values ('1.jpg');
+            insert into images (filename, size, last_used_at) values ('2.jpg', 20, {stale}), ('3.png', 30, {fresh}), ('6.jpg', 60, {stale});",
+            stale = stale,
+            fresh = now()
+        ))
+        .unwrap();
+
+        let report = collect_garbage(&db, &dir).unwrap();
+        // 2 is no longer used, 4 was never tracked, 6 is gone from disk.
+        assert_eq!(
+            filenames(&dir),
+            vec!["1.jpg", "1.list.jpg", "3.detail.jpg", "3.png", "5.part"]
+        );
+        assert_eq!(report.removed, 3);
+        assert_eq!(report.freed, 20 + 5 + 40);
+        let mut statement = db
+            .prepare("select filename from images order by filename")
+            .unwrap();
+        let tracked: Vec<String> = statement
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(tracked, vec!["1.jpg", "3.png"]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn evict_keeps_favorites_and_recently_used_images() {
+        let (dir, db) = setup("evict");
+        for filename in ["a.jpg", "b.jpg", "c.jpg", "d.jpg"] {
+            write(&dir, filename, 100);
+        }
+        db.execute_batch(
+            "insert into images (filename, size, last_used_at) values ('a.jpg', 100, 1), ('b.jpg', 100, 2), ('c.jpg', 100, 3), ('d.jpg', 100, 4);
+            insert into resources (id, pic) values (1, 'a.jpg'), (2, 'b.jpg'), (3, 'c.jpg');
+            insert into favorites (resource_id) values (2);
+            insert into metadata_fetches (resource_id, status, fetched_at) values (1, 'ok', 100);",
+        )
+        .unwrap();
+
+        // Under the cap, or without one, nothing goes.
+        assert_eq!(evict(&db, &dir, 400, "").unwrap().removed, 0);
+        assert_eq!(evict(&db, &dir, 0, "").unwrap().removed, 0);
+
+        let report = evict(&db, &dir, 250, "a.jpg").unwrap();
+        // `a` is kept as the image just saved, `b` as a favorite.
+        assert_eq!(report.removed, 2);
+        assert_eq!(report.freed, 200);
+        assert_eq!(report.size, 200);
+        assert_eq!(filenames(&dir), vec!["a.jpg", "b.jpg"]);
+
+        let report = evict(&db, &dir, 150, "").unwrap();
+        assert_eq!((report.removed, report.size), (1, 100));
+        assert_eq!(filenames(&dir), vec!["b.jpg"]);
+        // The resource loses its poster and is looked up again.
+        let (pic, fetched_at): (String, i64) = db
+            .query_row(
+                "select r.pic, m.fetched_at from resources as r inner join metadata_fetches as m on m.resource_id=r.id where r.id=1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((pic.as_str(), fetched_at), ("", 0));
+        let _ = fs::remove_dir_all(dir);
+    }
+}
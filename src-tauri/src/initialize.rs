@@ -1,25 +1,172 @@
 use anyhow::Result;
-use rusqlite::{params, Transaction};
+use rusqlite::{params, Connection, Transaction};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs::remove_file, path::PathBuf, thread};
-use tauri::{api::dialog, AppHandle, Window};
+use std::{
+    collections::HashMap,
+    fs::remove_file,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tauri::{api::dialog, command, AppHandle, Manager, State, Window};
 
 use crate::{
-    database::{self, connect, execute},
+    database::{self, connect, migrate},
+    links,
     response::Response,
     window::create_main_window,
-    yyets::{self, Item, RecordData, YYeTsSeason},
+    source::{DataSource, Format, JsonLinesSource, Parser, RawRecord, Resource, Season},
+    status::{self, InitializeState, InitializeStatus},
+    yyets::{self, YYeTsSource},
 };
 
-pub struct NameOnly {
-    pub id: i64,
-    pub name: String,
+/// Shared flag used to abort a running initialization from another thread.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    /// Set when the app closes rather than the user cancelling, so the run
+    /// can be resumed on the next start.
+    interrupted: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Aborts the run but keeps its state and dump for the next start.
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::SeqCst);
+        self.cancel();
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.load(Ordering::SeqCst)
+    }
+
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+        self.interrupted.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(anyhow::anyhow!("Initialization cancelled"));
+        }
+        Ok(())
+    }
+}
+
+/// Set while `initialize` runs, so a retry cannot start a second run writing
+/// the same staging database and state.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+const BATCH_SIZE: usize = 1000;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Ids of lookup rows keyed by their trimmed name.
+pub type NameCache = HashMap<String, i64>;
+
+#[derive(Default)]
+struct Lookups {
+    areas: NameCache,
+    channels: NameCache,
+    ways: NameCache,
+}
+
+impl Lookups {
+    /// Loads the existing lookup rows so that appending to a catalog reuses them.
+    fn load(connection: &Connection) -> Result<Self> {
+        Ok(Lookups {
+            areas: load_names(connection, "areas")?,
+            channels: load_names(connection, "channels")?,
+            ways: load_names(connection, "ways")?,
+        })
+    }
+}
+
+fn load_names(connection: &Connection, table: &str) -> Result<NameCache> {
+    let mut statement = connection.prepare(format!("select id, name from {}", table).as_str())?;
+    let mut rows = statement.query([])?;
+    let mut names = NameCache::new();
+    while let Some(row) = rows.next()? {
+        let name = row.get::<&str, String>("name")?;
+        names.insert(name.trim().to_string(), row.get("id")?);
+    }
+    Ok(names)
+}
+
+/// Report of the catalog import, shown after initialization.
+pub const INITIALIZE_REPORT: &str = "initialize";
+/// Report of the last `import_links`, appended to the live catalog.
+pub const LINKS_REPORT: &str = "links";
+
+pub struct ImportOptions {
+    /// Number of threads used to parse the raw payload of each batch.
+    pub workers: usize,
+    /// Whether to relax the journal and sync pragmas for the import. Only for
+    /// databases that can be thrown away, never the live one.
+    pub bulk_load: bool,
+    /// Report the failed records are stored under, replacing the rows of the
+    /// previous import with the same report.
+    pub report: &'static str,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            workers: thread::available_parallelism()
+                .map(|workers| workers.get())
+                .unwrap_or(1),
+            bulk_load: true,
+            report: INITIALIZE_REPORT,
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ImportSummary {
+    pub succeeded: i32,
+    pub failed: i32,
+}
+
+/// A record that could not be imported, tagged with the stage that failed.
+pub struct ImportFailure {
+    pub stage: &'static str,
+    pub error: anyhow::Error,
+}
+
+trait Stage<T> {
+    fn stage(self, stage: &'static str) -> Result<T, ImportFailure>;
+}
+
+impl<T, E: Into<anyhow::Error>> Stage<T> for Result<T, E> {
+    fn stage(self, stage: &'static str) -> Result<T, ImportFailure> {
+        self.map_err(|error| ImportFailure {
+            stage,
+            error: error.into(),
+        })
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct ResponseData<T> {
     step: String,
     data: T,
+    /// Seconds since the step started.
+    elapsed: f64,
+    /// Bytes or records processed per second.
+    rate: f64,
+    /// Estimated seconds until the step finishes.
+    eta: Option<f64>,
 }
 
 impl<T> ResponseData<T> {
@@ -27,19 +174,93 @@ impl<T> ResponseData<T> {
         ResponseData {
             step: step.to_string(),
             data,
+            elapsed: 0.0,
+            rate: 0.0,
+            eta: None,
         }
     }
 }
 
-pub fn initialize(window: Window, app_handle: AppHandle) {
+/// Limits progress events of a step to one per `PROGRESS_INTERVAL`, so the
+/// webview is not flooded with one message per chunk or record.
+pub struct ProgressThrottle {
+    step: &'static str,
+    started: Instant,
+    emitted: Option<Instant>,
+}
+
+impl ProgressThrottle {
+    pub fn new(step: &'static str) -> Self {
+        ProgressThrottle {
+            step,
+            started: Instant::now(),
+            emitted: None,
+        }
+    }
+
+    /// Returns the event to emit, or `None` while throttled. The final update
+    /// of a step is never throttled.
+    pub fn poll(&mut self, processed: u64, total: u64) -> Option<ResponseData<(u64, u64)>> {
+        let now = Instant::now();
+        let throttled = match self.emitted {
+            Some(emitted) => now.duration_since(emitted) < PROGRESS_INTERVAL,
+            None => false,
+        };
+        if throttled && processed < total {
+            return None;
+        }
+        self.emitted = Some(now);
+
+        let elapsed = now.duration_since(self.started).as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            processed as f64 / elapsed
+        } else {
+            0.0
+        };
+        let mut data = ResponseData::new(self.step, (processed, total));
+        data.elapsed = elapsed;
+        data.rate = rate;
+        if rate > 0.0 {
+            data.eta = Some(total.saturating_sub(processed) as f64 / rate);
+        }
+        Some(data)
+    }
+}
+
+/// Where initialization progress is reported: the initialize window, or
+/// stderr when running headless.
+#[derive(Clone)]
+pub enum Reporter {
+    Window(Window),
+    Console,
+}
+
+/// Starts the initialization unless the previous attempt failed, in which
+/// case the initialize window shows the failure and waits for a retry.
+pub fn resume(window: Window, app_handle: AppHandle) {
+    if status::current().status != InitializeStatus::Failed {
+        let _ = initialize(window, app_handle);
+    }
+}
+
+/// Starts the initialization in the background. Returns `false`, without
+/// doing anything, while a previous run is still going.
+pub fn initialize(window: Window, app_handle: AppHandle) -> bool {
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+    let token = app_handle.state::<CancellationToken>().inner().clone();
+    token.reset();
     thread::spawn(move || {
         tauri::async_runtime::block_on(async move {
-            let result = yyets::download(window.clone())
-                .await
-                .and_then(|db| import_series(&window, db));
-
-            match result {
-                Ok(_) => {
+            let reporter = Reporter::Window(window.clone());
+            match run(&reporter, &token).await {
+                Ok(summary) => {
+                    emit_initialize_event(
+                        &reporter,
+                        Response::ok("导入完成", ResponseData::new("finish", summary)),
+                    )
+                    .ok();
                     match create_main_window(&app_handle) {
                         Ok(main_window) => {
                             let _ = window.close();
@@ -50,138 +271,485 @@ pub fn initialize(window: Window, app_handle: AppHandle) {
                         }
                     }
                 }
+                Err(_) if token.is_cancelled() => {
+                    emit_initialize_event(
+                        &reporter,
+                        Response::ok("已取消", ResponseData::new("cancelled", ())),
+                    )
+                    .ok();
+                }
                 Err(e) => {
-                    emit_initialize_event(&window, Response::fail(e.to_string().as_str(), "")).ok();
+                    emit_initialize_event(&reporter, Response::fail(e.to_string().as_str(), "")).ok();
                 }
             };
         });
+        RUNNING.store(false, Ordering::SeqCst);
     });
+    true
 }
 
-fn import_series(window: &Window, db: PathBuf) -> Result<()> {
-    let yyets_connection = connect(&db)?;
+/// Downloads, or resumes, and imports the yyets dump, persisting every
+/// transition of the initialization state.
+pub async fn run(reporter: &Reporter, token: &CancellationToken) -> Result<ImportSummary> {
+    let (result, resumed) = match download_or_resume(reporter, token).await {
+        Ok((db, resumed)) => (import_dump(reporter, db, token), resumed),
+        Err(e) => (Err(e), false),
+    };
+    finish(&result, token, resumed);
+    result
+}
 
-    let total_size = yyets_connection.query_row("select count(*) as c from yyets", [], |row| {
-        let size = row.get::<&str, i32>("c")?;
-        Ok(size)
-    })?;
-    if total_size <= 0 {
-        let _ = remove_file(db);
-        return Ok(());
-    }
-
-    database::execute(|connection| {
-        let mut statement = yyets_connection.prepare("select * from yyets")?;
-        let mut result = statement.query([])?;
-        let mut saved = 0;
-        let mut areas: Vec<NameOnly> = vec![];
-        let mut channels: Vec<NameOnly> = vec![];
-        let mut ways: Vec<NameOnly> = vec![];
-        let trx = connection.transaction()?;
-        while let Some(row) = result.next()? {
-            let _ = Ok(()).and_then(|()| {
-                match row.get::<&str, String>("data") {
-                    Ok(data) => Ok(data),
-                    Err(e) => Err(anyhow::Error::new(e))
-                }
-            })
-            .and_then(|data| {
-                let record = serde_json::from_str::<RecordData>(data.as_str())?;
-                Ok(record)
-            })
-            .and_then(|record| {
-                let info = record.data.info;
-                let channel_id = get_id_by_name(&trx, "channels", info.channel, &mut channels);
-                let area_id = get_id_by_name(&trx, "areas", info.area, &mut areas);
-                let size = trx.execute(
-                "insert into resources (name, original_name, alias_name, channel_id, area_id) values (?1, ?2, ?3, ?4, ?5)",
-                    params![info.cnname, info.enname, info.aliasname, channel_id, area_id],
-                )?;
-                Ok((size, record.data.list))
-            })
-            .and_then(|(size, seasons)| {
-                if size <= 0 {
-                    return Ok(());
+/// Imports a yyets dump archive from disk instead of downloading it.
+pub fn import_archive(
+    reporter: &Reporter,
+    archive: &Path,
+    token: &CancellationToken,
+) -> Result<ImportSummary> {
+    let result = yyets::extract(archive).and_then(|db| {
+        status::save(&InitializeState {
+            status: InitializeStatus::Downloaded,
+            source: archive.to_string_lossy().to_string(),
+            dump: db.to_string_lossy().to_string(),
+            started_at: status::now(),
+            updated_at: status::now(),
+            ..InitializeState::default()
+        })?;
+        import_dump(reporter, db, token)
+    });
+    finish(&result, token, true);
+    result
+}
+
+fn import_dump(reporter: &Reporter, db: PathBuf, token: &CancellationToken) -> Result<ImportSummary> {
+    status::update(|state| state.status = InitializeStatus::Importing)?;
+    import_database(reporter, db, token)
+}
+
+/// Persists the outcome of a run. The dump is kept while the state refers to
+/// it: after an interruption, and after a failure so that a retry does not
+/// download it again. A dump that was already resumed once, or an archive
+/// given by the user, is dropped on failure.
+fn finish(result: &Result<ImportSummary>, token: &CancellationToken, resumed: bool) {
+    match result {
+        Ok(_) => {
+            let _ = status::update(|state| {
+                state.status = InitializeStatus::Complete;
+                state.dump = String::new();
+                state.error = String::new();
+            });
+        }
+        Err(_) if token.is_interrupted() => {}
+        Err(_) if token.is_cancelled() => {
+            let _ = status::save(&InitializeState::default());
+            yyets::clear_tmp_files();
+        }
+        Err(e) => {
+            let _ = status::update(|state| {
+                state.status = InitializeStatus::Failed;
+                if resumed {
+                    state.dump = String::new();
                 }
-                let resource_id = trx.last_insert_rowid();
-                insert_seasons(&trx, resource_id, seasons, &mut ways)?;
-                Ok(())
+                state.error = e.to_string();
             });
+            yyets::clear_tmp_files();
+        }
+    }
+}
 
-            saved += 1;
-            emit_initialize_event(
-                window,
-                Response::ok(
-                    "导入数据",
-                    ResponseData::new("importing", (saved, total_size)),
-                ),
-            )
-            .ok();
+/// Reuses the dump of an interrupted or failed run when it is still on disk,
+/// otherwise downloads a new one. Also tells whether the dump was reused.
+async fn download_or_resume(
+    reporter: &Reporter,
+    token: &CancellationToken,
+) -> Result<(PathBuf, bool)> {
+    let state = status::current();
+    if matches!(
+        state.status,
+        InitializeStatus::Downloaded | InitializeStatus::Importing | InitializeStatus::Failed
+    ) && !state.dump.is_empty()
+    {
+        let dump = PathBuf::from(&state.dump);
+        if dump.exists() {
+            status::update(|state| state.status = InitializeStatus::Downloaded)?;
+            return Ok((dump, true));
         }
-        trx.commit()?;
+    }
+
+    status::save(&InitializeState {
+        status: InitializeStatus::Downloading,
+        started_at: status::now(),
+        updated_at: status::now(),
+        ..InitializeState::default()
+    })?;
+    let (db, source) = yyets::download(reporter, token).await?;
+    status::update(|state| {
+        state.status = InitializeStatus::Downloaded;
+        state.dump = db.to_string_lossy().to_string();
+        state.source = source;
+    })?;
+    Ok((db, false))
+}
+
+#[command]
+pub async fn initialize_state() -> Response<InitializeState> {
+    Response::ok("success", status::current())
+}
+
+#[command]
+pub fn retry_initialize(window: Window, app_handle: AppHandle) -> Response<()> {
+    if !initialize(window, app_handle) {
+        return Response::fail("初始化正在进行中", ());
+    }
+    Response::ok("success", ())
+}
+
+#[command]
+pub fn cancel_initialize(token: State<'_, CancellationToken>) -> Response<()> {
+    token.cancel();
+    Response::ok("success", ())
+}
+
+#[command]
+pub async fn import_links(path: String) -> Response<ImportSummary> {
+    let result = JsonLinesSource::open(Path::new(&path)).and_then(|mut source| {
+        database::execute(|connection| {
+            import_series(
+                &mut source,
+                connection,
+                &CancellationToken::default(),
+                &ImportOptions {
+                    bulk_load: false,
+                    report: LINKS_REPORT,
+                    ..ImportOptions::default()
+                },
+                |_, _| {},
+            )
+        })
+    });
+    match result {
+        Ok(summary) => Response::ok("success", summary),
+        Err(e) => Response::fail(e.to_string().as_str(), ImportSummary::default()),
+    }
+}
+
+fn import_database(
+    reporter: &Reporter,
+    db: PathBuf,
+    token: &CancellationToken,
+) -> Result<ImportSummary> {
+    let staging = database::staging_db();
+    let _ = remove_file(&staging);
+    let result = import_staging(reporter, &db, &staging, token).and_then(|summary| {
+        token.check()?;
+        database::swap_catalog(&staging)?;
+        Ok(summary)
+    });
+    let _ = remove_file(&staging);
+    if result.is_ok() {
         let _ = remove_file(db);
-        Ok(())
-    })
+    }
+    result
+}
+
+/// Imports the dump into a fresh staging database and verifies it, so the
+/// live catalog is only ever replaced by a complete dataset.
+fn import_staging(
+    reporter: &Reporter,
+    db: &Path,
+    staging: &Path,
+    token: &CancellationToken,
+) -> Result<ImportSummary> {
+    let mut source = YYeTsSource::open(db)?;
+    let mut connection = connect(staging)?;
+    migrate(&connection)?;
+    let mut throttle = ProgressThrottle::new("importing");
+    let summary = import_series(
+        &mut source,
+        &mut connection,
+        token,
+        &ImportOptions::default(),
+        |processed, total| {
+            if let Some(data) = throttle.poll(processed as u64, total as u64) {
+                emit_initialize_event(reporter, Response::ok("导入数据", data)).ok();
+            }
+        },
+    )?;
+    verify_staging(&connection, &summary)?;
+    Ok(summary)
+}
+
+fn verify_staging(connection: &Connection, summary: &ImportSummary) -> Result<()> {
+    let check = connection.query_row("PRAGMA quick_check", [], |row| row.get::<usize, String>(0))?;
+    if check != "ok" {
+        return Err(anyhow::anyhow!("Imported database is corrupted: {}", check));
+    }
+    let resources = connection.query_row("select count(*) from resources", [], |row| {
+        row.get::<usize, i32>(0)
+    })?;
+    if resources <= 0 {
+        return Err(anyhow::anyhow!("No resource was imported"));
+    }
+    if resources != summary.succeeded {
+        return Err(anyhow::anyhow!(
+            "Imported {} resources but {} records succeeded",
+            resources,
+            summary.succeeded
+        ));
+    }
+    Ok(())
+}
+
+/// Imports every record of `source` into `connection` within a single
+/// transaction, reporting `(processed, total)` after each batch.
+pub fn import_series<F: FnMut(i32, i32)>(
+    source: &mut dyn DataSource,
+    connection: &mut Connection,
+    token: &CancellationToken,
+    options: &ImportOptions,
+    progress: F,
+) -> Result<ImportSummary> {
+    if options.bulk_load {
+        begin_bulk_load(connection)?;
+    }
+    let result = import_batches(source, connection, token, options, progress);
+    if options.bulk_load {
+        end_bulk_load(connection)?;
+    }
+    result
+}
+
+fn import_batches<F: FnMut(i32, i32)>(
+    source: &mut dyn DataSource,
+    connection: &mut Connection,
+    token: &CancellationToken,
+    options: &ImportOptions,
+    mut progress: F,
+) -> Result<ImportSummary> {
+    let total_size = source.total()?;
+    if total_size <= 0 {
+        return Ok(ImportSummary::default());
+    }
+
+    let parser = source.parser();
+    let mut summary = ImportSummary::default();
+    let mut failures: Vec<(i64, ImportFailure)> = vec![];
+    let mut lookups = Lookups::load(connection)?;
+    let mut trx = connection.transaction()?;
+    trx.execute("delete from import_errors where report=?1", [options.report])?;
+    loop {
+        let batch = source.read(BATCH_SIZE)?;
+        if batch.is_empty() {
+            break;
+        }
+        // Returning early drops the transaction, which rolls back every
+        // record imported so far.
+        token.check()?;
+        for (upstream_id, resource) in parse_records(batch, parser, options.workers)? {
+            match resource.and_then(|resource| import_record(&mut trx, resource, &mut lookups)) {
+                Ok(()) => summary.succeeded += 1,
+                Err(failure) => {
+                    summary.failed += 1;
+                    failures.push((upstream_id, failure));
+                }
+            }
+        }
+        progress(summary.succeeded + summary.failed, total_size);
+    }
+    for (upstream_id, failure) in failures {
+        trx.prepare_cached(
+            "insert into import_errors (upstream_id, stage, message, report) values (?1, ?2, ?3, ?4)",
+        )?
+        .execute(params![upstream_id, failure.stage, failure.error.to_string(), options.report])?;
+    }
+    trx.commit()?;
+    Ok(summary)
+}
+
+/// Relaxes durability while the catalog is rebuilt. The journal is kept, so an
+/// interrupted import is still rolled back on the next start.
+fn begin_bulk_load(connection: &Connection) -> Result<()> {
+    connection.execute_batch(
+        "PRAGMA journal_mode = WAL;
+        PRAGMA synchronous = OFF;
+        PRAGMA temp_store = MEMORY;
+        PRAGMA cache_size = -65536;",
+    )?;
+    Ok(())
+}
+
+fn end_bulk_load(connection: &Connection) -> Result<()> {
+    connection.execute_batch(
+        "PRAGMA synchronous = FULL;
+        PRAGMA journal_mode = DELETE;",
+    )?;
+    Ok(())
+}
+
+type ParsedRecord = (i64, Result<Resource, ImportFailure>);
+
+/// Parses a batch of raw records on up to `workers` threads, keeping the
+/// original order.
+fn parse_records(
+    batch: Vec<RawRecord>,
+    parser: Parser,
+    workers: usize,
+) -> Result<Vec<ParsedRecord>> {
+    if workers <= 1 || batch.len() <= 1 {
+        return Ok(batch
+            .into_iter()
+            .map(|record| parse_record(record, parser))
+            .collect());
+    }
+    let chunk_size = (batch.len() + workers - 1) / workers;
+    let mut batch = batch.into_iter();
+    let mut handles = vec![];
+    loop {
+        let chunk: Vec<RawRecord> = batch.by_ref().take(chunk_size).collect();
+        if chunk.is_empty() {
+            break;
+        }
+        handles.push(thread::spawn(move || {
+            chunk
+                .into_iter()
+                .map(|record| parse_record(record, parser))
+                .collect::<Vec<ParsedRecord>>()
+        }));
+    }
+    let mut records = vec![];
+    for handle in handles {
+        let parsed = handle
+            .join()
+            .map_err(|_| anyhow::anyhow!("Failed to parse records"))?;
+        records.extend(parsed);
+    }
+    Ok(records)
+}
+
+fn parse_record((upstream_id, data): RawRecord, parser: Parser) -> ParsedRecord {
+    let resource = data
+        .stage("read")
+        .and_then(|data| parser(data.as_str()).stage("parse"));
+    (upstream_id, resource)
+}
+
+fn import_record(
+    trx: &mut Transaction,
+    resource: Resource,
+    lookups: &mut Lookups,
+) -> Result<(), ImportFailure> {
+    // Lookup rows are shared between records, so they are created outside of
+    // the record savepoint to keep the cached ids valid after a rollback.
+    let channel_id = get_id_by_name(trx, "channels", &resource.channel, &mut lookups.channels)
+        .stage("channel")?;
+    let area_id =
+        get_id_by_name(trx, "areas", &resource.area, &mut lookups.areas).stage("area")?;
+    for season in resource.seasons.iter() {
+        for format in season.formats.iter() {
+            for link in format.episodes.iter().flat_map(|episode| episode.links.iter()) {
+                get_id_by_name(trx, "ways", &link.way_cn, &mut lookups.ways).stage("way")?;
+            }
+        }
+    }
+
+    let savepoint = trx.savepoint().stage("resource")?;
+    savepoint
+        .prepare_cached(
+            "insert into resources (name, original_name, alias_name, channel_id, area_id, upstream_id, show_type, expire, views, year, channel_cn) values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        )
+        .and_then(|mut statement| {
+            statement.execute(params![
+                resource.name,
+                resource.original_name,
+                resource.alias_name,
+                channel_id,
+                area_id,
+                resource.upstream_id,
+                resource.show_type,
+                resource.expire,
+                resource.views,
+                resource.year,
+                resource.channel_cn
+            ])
+        })
+        .stage("resource")?;
+    let resource_id = savepoint.last_insert_rowid();
+    insert_seasons(&savepoint, resource_id, resource.seasons, &mut lookups.ways)?;
+    savepoint.commit().stage("commit")?;
+    Ok(())
 }
 
 fn insert_seasons(
-    trx: &Transaction,
+    connection: &Connection,
     resource_id: i64,
-    seasons: Vec<YYeTsSeason>,
-    ways: &mut Vec<NameOnly>,
-) -> Result<()> {
+    seasons: Vec<Season>,
+    ways: &mut NameCache,
+) -> Result<(), ImportFailure> {
     for season in seasons {
-        let _ = trx
-            .execute(
-                "insert into seasons (resource_id, season, name) values (?1, ?2, ?3)",
-                params![resource_id, season.season_num, season.season_cn],
-            )
-            .and_then(|size| {
-                if size > 0 {
-                    let season_id = trx.last_insert_rowid();
-                    let _ = insert_formats(trx, season_id, season.formats, season.items, ways);
-                }
-                Ok(())
-            });
+        connection
+            .prepare_cached("insert into seasons (resource_id, season, name) values (?1, ?2, ?3)")
+            .and_then(|mut statement| {
+                statement.execute(params![resource_id, season.season, season.name])
+            })
+            .stage("season")?;
+        let season_id = connection.last_insert_rowid();
+        insert_formats(connection, season_id, season.formats, ways)?;
     }
     Ok(())
 }
 
 fn insert_formats(
-    trx: &Transaction,
+    connection: &Connection,
     season_id: i64,
-    formats: Vec<String>,
-    items: HashMap<String, Vec<Item>>,
-    ways: &mut Vec<NameOnly>,
-) -> Result<()> {
+    formats: Vec<Format>,
+    ways: &mut NameCache,
+) -> Result<(), ImportFailure> {
     for format in formats {
-        if let Ok(size) = trx.execute(
-            "insert into formats (season_id, format) values (?1, ?2)",
-            params![season_id, format],
-        ) {
-            if size > 0 {
-                if let Some(series) = items.get(format.as_str()) {
-                    let format_id = trx.last_insert_rowid();
-                    for item in series {
-                        if let Ok(size) = trx.execute(
-                        "insert into series (format_id, episode, name, size) values (?1, ?2, ?3, ?4)",
-                            params![format_id, item.episode, item.name, item.size],
-                        ) {
-                            if size > 0 {
-                                if let Some(files) = &item.files {
-                                    let series_id = trx.last_insert_rowid();
-                                    for file in files {
-                                        let way_id = get_id_by_name(trx, "ways", file.way_cn.clone(), ways);
-                                        let _ = trx.execute(
-                                            "insert into files (series_id, way_id, address, password) values (?1, ?2, ?3, ?4)",
-                                            params![series_id, way_id, file.address, file.passwd]
-                                        );
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+        connection
+            .prepare_cached("insert into formats (season_id, format) values (?1, ?2)")
+            .and_then(|mut statement| statement.execute(params![season_id, format.format]))
+            .stage("format")?;
+        let format_id = connection.last_insert_rowid();
+        for episode in format.episodes {
+            connection
+                .prepare_cached(
+                    "insert into series (format_id, episode, name, size, upstream_id, dateline, yyets_trans) values (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                )
+                .and_then(|mut statement| {
+                    statement.execute(params![
+                        format_id,
+                        episode.episode,
+                        episode.name,
+                        episode.size,
+                        episode.upstream_id,
+                        episode.dateline,
+                        episode.yyets_trans
+                    ])
+                })
+                .stage("series")?;
+            let series_id = connection.last_insert_rowid();
+            for link in episode.links {
+                let way_id = get_id_by_name(connection, "ways", &link.way_cn, ways).stage("way")?;
+                let parsed = links::parse(&link.address, &link.password);
+                connection
+                    .prepare_cached(
+                        "insert into files (series_id, way_id, address, password, way_code, link_kind, file_name, file_size, hash, trackers, drive) values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                    )
+                    .and_then(|mut statement| {
+                        statement.execute(params![
+                            series_id,
+                            way_id,
+                            link.address,
+                            parsed.password,
+                            link.way,
+                            parsed.kind,
+                            parsed.name,
+                            parsed.size,
+                            parsed.hash,
+                            parsed.trackers.join("\n"),
+                            parsed.drive
+                        ])
+                    })
+                    .stage("file")?;
             }
         }
     }
@@ -189,57 +757,34 @@ fn insert_formats(
 }
 
 fn get_id_by_name(
-    trx: &Transaction,
+    connection: &Connection,
     table: &str,
-    item: String,
-    collection: &mut Vec<NameOnly>,
-) -> i64 {
+    item: &str,
+    cache: &mut NameCache,
+) -> Result<i64> {
     let name = item.trim();
-    match collection.iter().find(|record| record.name.trim().eq(name)) {
-        Some(record) => record.id,
-        None => {
-            match trx.execute(
-                format!("insert into {} (name) values (?1)", table).as_str(),
-                params![name],
-            ) {
-                Ok(size) => {
-                    let mut id = 0;
-                    if size > 0 {
-                        id = trx.last_insert_rowid();
-                        collection.push(NameOnly {
-                            id,
-                            name: name.to_string(),
-                        });
-                    }
-                    id
-                }
-                Err(_) => 0,
-            }
-        }
+    if let Some(id) = cache.get(name) {
+        return Ok(*id);
     }
+    connection
+        .prepare_cached(format!("insert into {} (name) values (?1)", table).as_str())?
+        .execute(params![name])?;
+    let id = connection.last_insert_rowid();
+    cache.insert(name.to_string(), id);
+    Ok(id)
 }
 
 pub fn emit_initialize_event<T: serde::ser::Serialize>(
-    window: &Window,
+    reporter: &Reporter,
     response: Response<T>,
 ) -> Result<(), tauri::Error> {
-    window.emit("rubick_initialize", &response)
-}
-
-pub fn clear() {
-    let _ = execute(|connection| {
-        let _ = connection.execute_batch(
-            "delete from resources;
-            delete from areas;
-            delete from channels;
-            delete from files;
-            delete from formats;
-            delete from resources;
-            delete from seasons;
-            delete from series;
-            delete from ways;
-            delete from sqlite_sequence where name in ('resources', 'areas', 'channels', 'files', 'formats', 'resources', 'seasons', 'series', 'ways')",
-        );
-        Ok(())
-    });
+    match reporter {
+        Reporter::Window(window) => window.emit("rubick_initialize", &response),
+        Reporter::Console => {
+            if let Ok(json) = serde_json::to_string(&response) {
+                eprintln!("{}", json);
+            }
+            Ok(())
+        }
+    }
 }
@@ -0,0 +1,124 @@
+use tauri::{api::dialog, Manager};
+
+use crate::{
+    api::{resource, search, favorite, un_favorite, favorites, import_report, refresh_metadata, link_metadata, metadata_reviews},
+    http::test_connection,
+    images::clean_images,
+    initialize::{
+        cancel_initialize, import_links, initialize_state, retry_initialize, CancellationToken,
+    },
+    people::{person_resources, search_people},
+    prefetch::{cancel_prefetch, prefetch_favorites, prefetch_resources, Prefetcher},
+    settings::{import_douban_cookies, save_settings, settings},
+    window::{create_initialize_window, create_main_window},
+};
+
+mod api;
+mod application;
+pub mod cli;
+pub mod database;
+mod douban;
+mod http;
+mod images;
+pub mod initialize;
+mod links;
+mod matching;
+mod metadata;
+mod nfo;
+mod people;
+mod prefetch;
+mod provider;
+mod response;
+mod settings;
+pub mod source;
+mod status;
+mod tmdb;
+mod window;
+pub mod yyets;
+
+pub fn run() {
+    let context = tauri::generate_context!();
+    let app = tauri::Builder::default()
+        .manage(CancellationToken::default())
+        .manage(Prefetcher::default())
+        .setup(move |app| {
+            if app.windows().len() > 0 {
+                if let Some(window) = app.windows().values().next() {
+                    window.set_focus()?;
+                }
+                return Ok(());
+            }
+            application::setup(app)?;
+            database::setup()?;
+            if !application::initialized() {
+                let _ = create_initialize_window(app)?;
+            } else {
+                let _ = create_main_window(app)?;
+            }
+            Ok(())
+        })
+        .menu(if cfg!(target_os = "macos") {
+            tauri::Menu::os_default(&context.package_info().name)
+        } else {
+            tauri::Menu::default()
+        })
+        .invoke_handler(tauri::generate_handler![
+            search,
+            resource,
+            favorites,
+            favorite,
+            un_favorite,
+            import_report,
+            refresh_metadata,
+            link_metadata,
+            metadata_reviews,
+            prefetch_favorites,
+            prefetch_resources,
+            cancel_prefetch,
+            search_people,
+            person_resources,
+            clean_images,
+            cancel_initialize,
+            import_links,
+            initialize_state,
+            retry_initialize,
+            settings,
+            save_settings,
+            import_douban_cookies,
+            test_connection
+        ])
+        .build(context)
+        .expect("发生未知错误！");
+    app.run(|app_handle, e| match e {
+        tauri::RunEvent::Exit => {
+            if let Some(_window) = app_handle.get_window("initialize") {
+                // The dump is kept, the next start resumes from it.
+                app_handle.state::<CancellationToken>().interrupt();
+                yyets::clear_tmp_files();
+                database::remove_staging();
+            }
+        }
+        tauri::RunEvent::Ready => {
+            if let Some(window) = app_handle.get_window("initialize") {
+                match window.show()
+                    .and_then(|_| {
+                        initialize::resume(window.clone(), app_handle.app_handle());
+                        Ok(())
+                    }) {
+                    Ok(_) => {}
+                    Err(e) => dialog::message(Some(&window), "Error", e.to_string().as_str()),
+                };
+                return;
+            }
+            if let Some(window) = app_handle.get_window("main") {
+                match window.show() {
+                    Ok(_) => prefetch::resume(app_handle.app_handle()),
+                    Err(e) => dialog::message(Some(&window), "Error", e.to_string().as_str()),
+                };
+                return;
+            };
+            panic!("There is no any window active");
+        }
+        _ => {}
+    });
+}
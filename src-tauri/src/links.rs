@@ -0,0 +1,300 @@
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+pub const MAGNET: &str = "magnet";
+pub const ED2K: &str = "ed2k";
+pub const CLOUD: &str = "cloud";
+pub const HTTP: &str = "http";
+
+/// Cloud drives recognised by the host of their share links, as
+/// `(host suffix, drive)`.
+const DRIVES: [(&str, &str); 10] = [
+    ("pan.baidu.com", "baidu"),
+    ("yun.baidu.com", "baidu"),
+    ("aliyundrive.com", "aliyun"),
+    ("alipan.com", "aliyun"),
+    ("pan.quark.cn", "quark"),
+    ("115.com", "115"),
+    ("115cdn.com", "115"),
+    ("share.weiyun.com", "weiyun"),
+    ("cloud.189.cn", "189"),
+    ("123pan.com", "123pan"),
+];
+/// Labels a share password follows when pasted along with the link.
+const PASSWORD_LABELS: [&str; 4] = ["提取码", "访问码", "密码", "pwd"];
+const BASE32: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// What the address of a file points to. Fields the address does not carry
+/// are empty, or zero for the size.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ParsedLink {
+    /// `magnet`, `ed2k`, `cloud`, `http`, or empty when not recognised.
+    pub kind: String,
+    /// File name, from `dn` of magnets or the name of ed2k links.
+    pub name: String,
+    /// Size in bytes.
+    pub size: i64,
+    /// BitTorrent info hash as lowercase hex, or the MD4 hash of ed2k links.
+    pub hash: String,
+    pub trackers: Vec<String>,
+    /// Cloud drive of a share link, e.g. `baidu`.
+    pub drive: String,
+    /// Share password, either given with the link or found in the address.
+    pub password: String,
+}
+
+/// Parses the address of a file. `password` is the one stored with the link,
+/// kept when the address does not carry its own.
+pub fn parse(address: &str, password: &str) -> ParsedLink {
+    let address = address.trim();
+    let mut link = if has_scheme(address, "magnet:") {
+        parse_magnet(address)
+    } else if has_scheme(address, "ed2k:") {
+        parse_ed2k(address)
+    } else {
+        parse_share(address)
+    };
+    if !password.trim().is_empty() {
+        link.password = password.trim().to_string();
+    }
+    link
+}
+
+fn has_scheme(address: &str, scheme: &str) -> bool {
+    address
+        .get(..scheme.len())
+        .unwrap_or_default()
+        .eq_ignore_ascii_case(scheme)
+}
+
+fn parse_magnet(address: &str) -> ParsedLink {
+    let mut link = ParsedLink {
+        kind: MAGNET.to_string(),
+        ..ParsedLink::default()
+    };
+    let url = match Url::parse(address) {
+        Ok(url) => url,
+        Err(_) => return link,
+    };
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "xt" if link.hash.is_empty() => {
+                if let Some(hash) = value.strip_prefix("urn:btih:") {
+                    link.hash = btih_hex(hash);
+                }
+            }
+            "dn" => link.name = value.trim().to_string(),
+            "xl" => link.size = value.parse().unwrap_or(0),
+            "tr" if !link.trackers.contains(&value.to_string()) => {
+                link.trackers.push(value.to_string())
+            }
+            _ => {}
+        }
+    }
+    link
+}
+
+/// Info hashes come as 40 hex digits or 32 base32 characters.
+fn btih_hex(hash: &str) -> String {
+    if hash.len() != 32 {
+        return hash.to_ascii_lowercase();
+    }
+    let mut bytes = vec![];
+    let (mut buffer, mut bits) = (0_u64, 0);
+    for c in hash.to_ascii_uppercase().bytes() {
+        let value = match BASE32.iter().position(|b| *b == c) {
+            Some(value) => value as u64,
+            None => return hash.to_ascii_lowercase(),
+        };
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `ed2k://|file|<name>|<size>|<hash>|/`, optionally followed by more fields
+/// such as `h=<aich>|`.
+fn parse_ed2k(address: &str) -> ParsedLink {
+    let mut link = ParsedLink {
+        kind: ED2K.to_string(),
+        ..ParsedLink::default()
+    };
+    let fields: Vec<&str> = address.split('|').collect();
+    if fields.len() >= 5 && fields[1].eq_ignore_ascii_case("file") {
+        link.name = percent_decode(fields[2]);
+        link.size = fields[3].trim().parse().unwrap_or(0);
+        link.hash = fields[4].trim().to_ascii_lowercase();
+    }
+    link
+}
+
+/// A cloud drive share link, possibly pasted as `链接: <url> 提取码: abcd`.
+fn parse_share(address: &str) -> ParsedLink {
+    let mut link = ParsedLink::default();
+    let url = address.split_whitespace().find_map(|word| {
+        let start = word.find("http://").or_else(|| word.find("https://"))?;
+        Url::parse(&word[start..]).ok()
+    });
+    let url = match url {
+        Some(url) => url,
+        None => return link,
+    };
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    link.drive = DRIVES
+        .iter()
+        .find(|(suffix, _)| host == *suffix || host.ends_with(format!(".{}", suffix).as_str()))
+        .map(|(_, drive)| drive.to_string())
+        .or_else(|| host.contains("lanzou").then(|| "lanzou".to_string()))
+        .unwrap_or_default();
+    if link.drive.is_empty() {
+        link.kind = HTTP.to_string();
+        return link;
+    }
+    link.kind = CLOUD.to_string();
+    link.password = url
+        .query_pairs()
+        .find(|(key, _)| key == "pwd" || key == "password")
+        .map(|(_, value)| value.to_string())
+        .or_else(|| labelled_password(address))
+        .unwrap_or_default();
+    link
+}
+
+/// The word following one of `PASSWORD_LABELS` and a separator.
+fn labelled_password(address: &str) -> Option<String> {
+    PASSWORD_LABELS.iter().find_map(|label| {
+        let (_, rest) = address.split_once(label)?;
+        let password: String = rest
+            .trim_start_matches([':', '：', '=', ' '])
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect();
+        if password.is_empty() {
+            None
+        } else {
+            Some(password)
+        }
+    })
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = value.get(i + 1..i + 3).unwrap_or_default();
+            if hex.len() == 2 && hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                let byte = u8::from_str_radix(hex, 16).unwrap_or_default();
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "c12fe1c06bba254a9dc9f519b335aa7c1367a88a";
+
+    fn link(kind: &str) -> ParsedLink {
+        ParsedLink {
+            kind: kind.to_string(),
+            ..ParsedLink::default()
+        }
+    }
+
+    #[test]
+    fn parse_recognises_links() {
+        let cases = [
+            (
+                "magnet:?xt=urn:btih:C12FE1C06BBA254A9DC9F519B335AA7C1367A88A&dn=Show.S01E01.mkv&xl=536870912&tr=udp%3A%2F%2Ftracker.example.org%3A1337&tr=udp%3A%2F%2Ftracker.example.org%3A1337&tr=http%3A%2F%2Fbt.example.com%2Fannounce",
+                "",
+                ParsedLink {
+                    name: "Show.S01E01.mkv".to_string(),
+                    size: 536870912,
+                    hash: HASH.to_string(),
+                    trackers: vec![
+                        "udp://tracker.example.org:1337".to_string(),
+                        "http://bt.example.com/announce".to_string(),
+                    ],
+                    ..link(MAGNET)
+                },
+            ),
+            (
+                " MAGNET:?xt=urn:btih:YEX6DQDLXISUVHOJ6UM3GNNKPQJWPKEK&dn=Show+S01E02&xl=abc ",
+                "",
+                ParsedLink {
+                    name: "Show S01E02".to_string(),
+                    hash: HASH.to_string(),
+                    ..link(MAGNET)
+                },
+            ),
+            (
+                "ed2k://|file|%E7%BB%9D%E5%91%BD%E6%AF%92%E5%B8%88.S01E01.mkv|536870912|0123456789ABCDEF0123456789ABCDEF|h=XYZ|/",
+                "",
+                ParsedLink {
+                    name: "绝命毒师.S01E01.mkv".to_string(),
+                    size: 536870912,
+                    hash: "0123456789abcdef0123456789abcdef".to_string(),
+                    ..link(ED2K)
+                },
+            ),
+            ("ed2k://|server|1.2.3.4|4661|/", "", link(ED2K)),
+            (
+                "链接: https://pan.baidu.com/s/1AbCdEf 提取码: abcd 复制这段内容后打开百度网盘",
+                "",
+                ParsedLink {
+                    drive: "baidu".to_string(),
+                    password: "abcd".to_string(),
+                    ..link(CLOUD)
+                },
+            ),
+            (
+                "https://www.aliyundrive.com/s/xYz123?pwd=9k2m",
+                "",
+                ParsedLink {
+                    drive: "aliyun".to_string(),
+                    password: "9k2m".to_string(),
+                    ..link(CLOUD)
+                },
+            ),
+            (
+                "https://pan.quark.cn/s/abc 提取码：zz99",
+                " q1w2 ",
+                ParsedLink {
+                    drive: "quark".to_string(),
+                    password: "q1w2".to_string(),
+                    ..link(CLOUD)
+                },
+            ),
+            (
+                "https://wwa.lanzoux.com/iAbc",
+                "",
+                ParsedLink {
+                    drive: "lanzou".to_string(),
+                    ..link(CLOUD)
+                },
+            ),
+            ("https://example.com/Show.S01E01.mkv", "", link(HTTP)),
+            // Prefixes cut inside a character must not panic.
+            ("磁力magnet:?xt=urn:btih:C12FE1C06BBA254A9DC9F519B335AA7C1367A88A", "", link("")),
+            ("电驴ed2k://|file|a.mkv|1|0123456789abcdef0123456789abcdef|/", "", link("")),
+            ("é", "", link("")),
+            ("", "", link("")),
+        ];
+        for (address, password, expected) in cases {
+            assert_eq!(parse(address, password), expected, "{}", address);
+        }
+    }
+}
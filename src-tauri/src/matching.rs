@@ -0,0 +1,292 @@
+use crate::api::Resource;
+
+/// A subject listed in the search results of a metadata provider.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub id: String,
+    pub title: String,
+    pub original_title: String,
+    pub year: Option<i32>,
+    /// e.g. `电视剧` or `电影`, empty when the provider does not say.
+    pub category: String,
+}
+
+/// What is known about a resource when looking for its subject.
+pub struct Query<'a> {
+    pub name: &'a str,
+    pub original_name: &'a str,
+    pub alias_name: &'a str,
+    /// `/`-joined years from the upstream catalog.
+    pub year: &'a str,
+    /// Upstream channel, `tv` or `movie`.
+    pub channel: &'a str,
+}
+
+impl<'a> From<&'a Resource> for Query<'a> {
+    fn from(resource: &'a Resource) -> Self {
+        Query {
+            name: resource.name.as_str(),
+            original_name: resource.original_name.as_str(),
+            alias_name: resource.alias_name.as_str(),
+            year: resource.year.as_str(),
+            channel: resource.channel.as_str(),
+        }
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+/// Dice coefficient of the character bigrams of two normalized names.
+fn similarity(a: &str, b: &str) -> f64 {
+    let bigrams = |s: &str| {
+        let chars: Vec<char> = s.chars().collect();
+        chars
+            .windows(2)
+            .map(|pair| (pair[0], pair[1]))
+            .collect::<Vec<(char, char)>>()
+    };
+    let (a, mut b) = (bigrams(a), bigrams(b));
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let total = (a.len() + b.len()) as f64;
+    let mut common = 0;
+    for pair in a {
+        if let Some(index) = b.iter().position(|other| *other == pair) {
+            b.swap_remove(index);
+            common += 1;
+        }
+    }
+    2.0 * common as f64 / total
+}
+
+fn name_score(candidate: &Candidate, query: &Query) -> f64 {
+    let names: Vec<String> = [query.name, query.original_name]
+        .into_iter()
+        .chain(query.alias_name.split(['/', ',', '，', '、']))
+        .map(normalize)
+        .filter(|name| !name.is_empty())
+        .collect();
+    let mut best: f64 = 0.0;
+    for title in [&candidate.title, &candidate.original_title] {
+        let title = normalize(title);
+        if title.is_empty() {
+            continue;
+        }
+        for name in names.iter() {
+            let score = if title.eq(name) {
+                1.0
+            } else if title.starts_with(name.as_str()) || name.starts_with(title.as_str()) {
+                0.8
+            } else if title.contains(name.as_str()) || name.contains(title.as_str()) {
+                0.6
+            } else {
+                similarity(&title, name) * 0.6
+            };
+            best = best.max(score);
+        }
+    }
+    best
+}
+
+fn year_score(candidate: &Candidate, query: &Query) -> f64 {
+    let years: Vec<i32> = query
+        .year
+        .split('/')
+        .filter_map(|year| year.trim().parse::<i32>().ok())
+        .collect();
+    match candidate.year {
+        Some(year) if !years.is_empty() => {
+            let distance = years
+                .iter()
+                .map(|other| (year - other).abs())
+                .min()
+                .unwrap_or(0);
+            match distance {
+                0 => 1.0,
+                1 => 0.5,
+                _ => 0.0,
+            }
+        }
+        _ => 0.5,
+    }
+}
+
+fn category_score(candidate: &Candidate, query: &Query) -> f64 {
+    let expected = match query.channel {
+        "tv" => "电视剧",
+        "movie" => "电影",
+        _ => return 0.5,
+    };
+    if candidate.category.is_empty() {
+        0.5
+    } else if candidate.category.contains(expected) {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// Scores a candidate between 0 and 1 against the resource.
+pub fn score(candidate: &Candidate, query: &Query) -> f64 {
+    name_score(candidate, query) * 0.6
+        + year_score(candidate, query) * 0.25
+        + category_score(candidate, query) * 0.15
+}
+
+/// Picks the highest scoring candidate, preferring earlier results on ties.
+pub fn best_match(candidates: Vec<Candidate>, query: &Query) -> Option<(Candidate, f64)> {
+    let mut best: Option<(Candidate, f64)> = None;
+    for candidate in candidates {
+        let score = score(&candidate, query);
+        if best.as_ref().map(|(_, best)| score > *best).unwrap_or(true) {
+            best = Some((candidate, score));
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(
+        id: &str,
+        title: &str,
+        original_title: &str,
+        year: i32,
+        category: &str,
+    ) -> Candidate {
+        Candidate {
+            id: id.to_string(),
+            title: title.to_string(),
+            original_title: original_title.to_string(),
+            year: Some(year),
+            category: category.to_string(),
+        }
+    }
+
+    fn query<'a>(
+        name: &'a str,
+        original_name: &'a str,
+        year: &'a str,
+        channel: &'a str,
+    ) -> Query<'a> {
+        Query {
+            name,
+            original_name,
+            alias_name: "",
+            year,
+            channel,
+        }
+    }
+
+    #[test]
+    fn best_match_scores_candidates() {
+        let cases = [
+            // A remake with the same title, told apart by year and category.
+            (
+                query("西部世界", "Westworld", "2016", "tv"),
+                vec![
+                    candidate("1973", "西部世界", "Westworld", 1973, "电影"),
+                    candidate("2016", "西部世界", "Westworld", 2016, "电视剧"),
+                ],
+                "2016",
+                1.0,
+            ),
+            // Shows of the same name from different countries.
+            (
+                query("无耻之徒", "Shameless", "2011", "tv"),
+                vec![
+                    candidate("uk", "无耻之徒", "Shameless", 2004, "电视剧"),
+                    candidate("us", "无耻之徒(美版)", "Shameless", 2011, "电视剧"),
+                ],
+                "us",
+                1.0,
+            ),
+            // A year off by one, e.g. a show that aired late in December.
+            (
+                query("曼达洛人", "The Mandalorian", "2018", "tv"),
+                vec![candidate(
+                    "1",
+                    "曼达洛人",
+                    "The Mandalorian",
+                    2019,
+                    "电视剧",
+                )],
+                "1",
+                0.6 + 0.5 * 0.25 + 0.15,
+            ),
+            // No year to compare, the category decides.
+            (
+                query("傲慢与偏见", "Pride and Prejudice", "", "movie"),
+                vec![
+                    candidate("tv", "傲慢与偏见", "Pride and Prejudice", 1995, "电视剧"),
+                    candidate("movie", "傲慢与偏见", "Pride and Prejudice", 2005, "电影"),
+                ],
+                "movie",
+                0.6 + 0.5 * 0.25 + 0.15,
+            ),
+            // Title prefixes, e.g. a season suffix, score lower than exact names.
+            (
+                query("绝命毒师", "Breaking Bad", "2008", "tv"),
+                vec![candidate(
+                    "1",
+                    "绝命毒师 第一季",
+                    "Breaking Bad Season 1",
+                    2008,
+                    "电视剧",
+                )],
+                "1",
+                0.8 * 0.6 + 0.25 + 0.15,
+            ),
+            // Ties go to the earlier result.
+            (
+                query("黑镜", "Black Mirror", "2011", ""),
+                vec![
+                    candidate("first", "黑镜", "Black Mirror", 2011, ""),
+                    candidate("second", "黑镜", "Black Mirror", 2011, ""),
+                ],
+                "first",
+                0.6 + 0.25 + 0.5 * 0.15,
+            ),
+        ];
+        for (query, candidates, id, score) in cases {
+            let (candidate, confidence) = best_match(candidates, &query).unwrap();
+            assert_eq!(candidate.id, id, "{}", query.name);
+            assert!(
+                (confidence - score).abs() < 1e-9,
+                "{}: {}",
+                query.name,
+                confidence
+            );
+        }
+    }
+
+    #[test]
+    fn score_penalises_a_category_mismatch() {
+        let query = query("傲慢与偏见", "Pride and Prejudice", "2005", "movie");
+        let series = candidate("1", "傲慢与偏见", "Pride and Prejudice", 2005, "电视剧");
+        assert!((score(&series, &query) - 0.85).abs() < 1e-9);
+    }
+
+    #[test]
+    fn score_matches_aliases() {
+        let query = Query {
+            alias_name: "制毒师/超越罪恶",
+            ..query("绝命毒师", "Breaking Bad", "2008", "tv")
+        };
+        let candidate = candidate("1", "超越罪恶", "", 2008, "");
+        assert!((score(&candidate, &query) - (0.6 + 0.25 + 0.5 * 0.15)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn best_match_of_no_candidates_is_none() {
+        assert!(best_match(vec![], &query("黑镜", "", "", "tv")).is_none());
+    }
+}
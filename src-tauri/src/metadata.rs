@@ -0,0 +1,380 @@
+use std::{collections::HashSet, sync::Mutex};
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::Resource,
+    database::execute,
+    douban::parse_subject_id,
+    people,
+    provider::{self, Metadata},
+    settings,
+    status::now,
+};
+
+/// Seconds before a successfully fetched resource is looked up again.
+const TTL: i64 = 30 * 24 * 60 * 60;
+/// Seconds to wait after the first failure, doubled on every further one.
+const BACKOFF: i64 = 60 * 60;
+/// Matches scoring below this are flagged for review.
+const REVIEW_THRESHOLD: f64 = 0.6;
+
+/// Resources being looked up, on demand or by the prefetcher, so none is
+/// looked up twice at the same time.
+static ENRICHING: Lazy<Mutex<HashSet<i64>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// The last metadata lookup of a resource.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataFetch {
+    pub resource_id: i64,
+    /// Provider the subject was found by, e.g. `douban`.
+    pub provider: String,
+    pub subject_id: String,
+    /// `ok` or `failed`.
+    pub status: String,
+    pub error: String,
+    /// Consecutive failures since the last successful lookup.
+    pub attempts: i64,
+    pub fetched_at: i64,
+    /// Score of the chosen subject, between 0 and 1.
+    pub confidence: f64,
+    pub needs_review: bool,
+    /// Linked by hand; refreshes reuse `subject_id` instead of searching.
+    pub pinned: bool,
+    /// Fields a provider expected but could not parse, as
+    /// `<provider>.<field>` separated by commas. Usually means the provider's
+    /// markup changed.
+    pub missing: String,
+}
+
+impl MetadataFetch {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(MetadataFetch {
+            resource_id: row.get("resource_id")?,
+            provider: row.get("provider")?,
+            subject_id: row.get("subject_id")?,
+            status: row.get("status")?,
+            error: row.get("error")?,
+            attempts: row.get("attempts")?,
+            fetched_at: row.get("fetched_at")?,
+            confidence: row.get("confidence")?,
+            needs_review: row.get("needs_review")?,
+            pinned: row.get("pinned")?,
+            missing: row.get("missing")?,
+        })
+    }
+
+    /// Time after which the resource may be looked up again.
+    pub fn next_fetch_at(&self) -> i64 {
+        if self.status == "ok" {
+            return self.fetched_at + TTL;
+        }
+        let exponent = (self.attempts - 1).clamp(0, 16) as u32;
+        self.fetched_at + (BACKOFF * 2_i64.pow(exponent)).min(TTL)
+    }
+}
+
+pub fn load(db: &Connection, resource_id: i64) -> Result<Option<MetadataFetch>> {
+    let fetch = db
+        .query_row(
+            "select * from metadata_fetches where resource_id=?1",
+            [resource_id],
+            MetadataFetch::from_row,
+        )
+        .optional()?;
+    Ok(fetch)
+}
+
+/// Whether the metadata of `resource` is missing or stale, and the TTL or
+/// backoff window of its last lookup has passed.
+pub fn should_fetch(resource: &Resource) -> bool {
+    match execute(|db| load(db, resource.id)) {
+        Ok(Some(fetch)) => now() >= fetch.next_fetch_at(),
+        Ok(None) => resource.pic.is_empty(),
+        Err(_) => false,
+    }
+}
+
+/// Claims `resource_id` for a lookup. Returns `false` while another one is
+/// running, otherwise `finish_enriching` must follow.
+pub fn start_enriching(resource_id: i64) -> bool {
+    ENRICHING.lock().unwrap().insert(resource_id)
+}
+
+pub fn finish_enriching(resource_id: i64) {
+    ENRICHING.lock().unwrap().remove(&resource_id);
+}
+
+/// The provider and subject a lookup settled on, with its confidence and the
+/// fields the providers failed to parse.
+type Matched = (&'static str, String, f64, Vec<String>);
+
+/// Looks `resource` up through the configured providers, stores the merged
+/// result and records the attempt. A pinned subject is looked up again
+/// instead of searching for a new one.
+pub async fn fetch(resource: &mut Resource) -> Result<()> {
+    let pinned = execute(|db| load_pin(db, resource.id, resource.upstream_id))?;
+    let pinned = pinned
+        .as_ref()
+        .map(|(provider, subject_id)| (provider.as_str(), subject_id.as_str()));
+    let result = enrich(resource, pinned).await;
+    let _ = execute(|db| record(db, resource.id, &result, pinned));
+    result.map(|_| ())
+}
+
+/// Links `resource` to the Douban subject given as an url or id, overwriting
+/// its metadata and pinning the subject for later refreshes. The pin is kept
+/// even when the subject cannot be scraped right now.
+pub async fn link(resource: &mut Resource, subject: &str) -> Result<()> {
+    let subject_id = parse_subject_id(subject)?;
+    let pinned = Some(("douban", subject_id.as_str()));
+    execute(|db| save_pin(db, resource.upstream_id, "douban", subject_id.as_str()))?;
+    let result = enrich(resource, pinned).await;
+    execute(|db| record(db, resource.id, &result, pinned))?;
+    result.map(|_| ())
+}
+
+/// The subject linked by hand to a resource, as `(provider, subject_id)`.
+/// Pins are keyed by upstream id so they survive an import, which renumbers
+/// resources. Resources without one, e.g. of catalogs imported before it was
+/// stored, keep their pin in `metadata_fetches` only.
+fn load_pin(
+    db: &Connection,
+    resource_id: i64,
+    upstream_id: i64,
+) -> Result<Option<(String, String)>> {
+    if upstream_id == 0 {
+        return Ok(load(db, resource_id)?
+            .filter(|fetch| fetch.pinned && !fetch.subject_id.is_empty())
+            .map(|fetch| (fetch.provider, fetch.subject_id)));
+    }
+    let pin = db
+        .query_row(
+            "select provider, subject_id from metadata_pins where upstream_id=?1 and subject_id<>''",
+            [upstream_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    Ok(pin)
+}
+
+/// Does nothing for resources without an upstream id, which would all share
+/// the pin.
+fn save_pin(db: &Connection, upstream_id: i64, provider: &str, subject_id: &str) -> Result<()> {
+    if upstream_id == 0 {
+        return Ok(());
+    }
+    db.execute(
+        "insert or replace into metadata_pins (upstream_id, provider, subject_id, pinned_at) values (?1, ?2, ?3, ?4)",
+        params![upstream_id, provider, subject_id, now()],
+    )?;
+    Ok(())
+}
+
+/// Asks every provider in turn, the pinned one first, and merges their
+/// answers field by field.
+async fn enrich(resource: &mut Resource, pinned: Option<(&str, &str)>) -> Result<Matched> {
+    let settings = settings::load().metadata;
+    let mut providers = provider::providers(&settings);
+    if let Some((name, _)) = pinned {
+        providers.retain(|provider| provider.name() != name);
+        if let Some(provider) = provider::provider(name, &settings) {
+            providers.insert(0, provider);
+        }
+    }
+
+    let mut merged: Option<(&'static str, Metadata)> = None;
+    let mut pic = None;
+    let mut errors = vec![];
+    let mut missing = vec![];
+    for provider in providers.iter() {
+        let subject_id = pinned
+            .filter(|(name, _)| *name == provider.name())
+            .map(|(_, subject_id)| subject_id);
+        let metadata = match provider.lookup(resource, subject_id).await {
+            Ok(Some(metadata)) => metadata,
+            Ok(None) => continue,
+            Err(e) => {
+                errors.push(format!("{}: {}", provider.name(), e));
+                continue;
+            }
+        };
+        missing.extend(
+            metadata
+                .missing
+                .iter()
+                .map(|field| format!("{}.{}", provider.name(), field)),
+        );
+        if pic.is_none() && !metadata.pic.is_empty() {
+            pic = provider.image(metadata.pic.as_str()).await.ok();
+        }
+        match merged.as_mut() {
+            Some((_, merged)) => merged.merge(&metadata),
+            None => merged = Some((provider.name(), metadata)),
+        }
+    }
+
+    let (provider, metadata) = merged.ok_or_else(|| {
+        if errors.is_empty() {
+            anyhow::anyhow!("No metadata provider found the resource")
+        } else {
+            anyhow::anyhow!(errors.join("; "))
+        }
+    })?;
+    if let Some(pic) = pic {
+        resource.pic = pic;
+        resource.update_thumbnails();
+    }
+    resource.directors = metadata.directors.to_string();
+    resource.writers = metadata.writers.to_string();
+    resource.actors = metadata.actors.to_string();
+    resource.types = metadata.types.to_string();
+    resource.released_at = metadata.released_at.to_string();
+    resource.summary = metadata.summary.to_string();
+    resource.rating = metadata.rating;
+    resource.votes = metadata.votes;
+    resource.rating_distribution = metadata.rating_distribution.clone();
+    resource.imdb_id = metadata.imdb_id.to_string();
+    resource.episodes = metadata.episodes;
+    resource.runtime = metadata.runtime;
+    resource.countries = metadata.countries.to_string();
+    resource.languages = metadata.languages.to_string();
+    execute(|db| {
+        update_resource(db, resource)?;
+        people::save_credits(db, resource.id, &metadata.credits)
+    })?;
+    Ok((provider, metadata.subject_id, metadata.confidence, missing))
+}
+
+fn record(
+    db: &Connection,
+    resource_id: i64,
+    result: &Result<Matched>,
+    pinned: Option<(&str, &str)>,
+) -> Result<()> {
+    match result {
+        Ok((provider, subject_id, confidence, missing)) => db.execute(
+            "insert or replace into metadata_fetches (resource_id, provider, subject_id, status, error, attempts, fetched_at, confidence, needs_review, pinned, missing) values (?1, ?2, ?3, 'ok', '', 0, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                resource_id,
+                provider,
+                subject_id,
+                now(),
+                confidence,
+                pinned.is_none() && *confidence < REVIEW_THRESHOLD,
+                pinned.is_some(),
+                missing.join(",")
+            ],
+        )?,
+        // A failed lookup of a pinned subject still records the pin.
+        Err(e) => db.execute(
+            "insert into metadata_fetches (resource_id, status, error, attempts, fetched_at, provider, subject_id, pinned) values (?1, 'failed', ?2, 1, ?3, ?4, ?5, ?6)
+            on conflict (resource_id) do update set status='failed', error=excluded.error, attempts=attempts+1, fetched_at=excluded.fetched_at,
+            provider=case when excluded.pinned then excluded.provider else provider end,
+            subject_id=case when excluded.pinned then excluded.subject_id else subject_id end,
+            pinned=excluded.pinned or pinned",
+            params![
+                resource_id,
+                e.to_string(),
+                now(),
+                pinned.map(|(provider, _)| provider).unwrap_or("douban"),
+                pinned.map(|(_, subject_id)| subject_id).unwrap_or_default(),
+                pinned.is_some()
+            ],
+        )?,
+    };
+    Ok(())
+}
+
+/// A match scoring below the review threshold.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataReview {
+    pub name: String,
+    #[serde(flatten)]
+    pub fetch: MetadataFetch,
+}
+
+/// Resources whose subject was matched with a low confidence, least
+/// confident first.
+pub fn reviews(db: &mut Connection) -> Result<Vec<MetadataReview>> {
+    let mut stmt = db.prepare(
+        "select m.*, r.name from metadata_fetches as m inner join resources as r on r.id=m.resource_id where m.needs_review=1 order by m.confidence asc",
+    )?;
+    let mut rows = stmt.query([])?;
+    let mut reviews = vec![];
+    while let Some(row) = rows.next()? {
+        reviews.push(MetadataReview {
+            name: row.get("name")?,
+            fetch: MetadataFetch::from_row(row)?,
+        });
+    }
+    Ok(reviews)
+}
+
+fn update_resource(db: &mut Connection, resource: &Resource) -> Result<usize> {
+    let size = db.execute("update resources set pic=?1, directors=?2, writers=?3, actors=?4, types=?5, released_at=?6, summary=?7, rating=?8, votes=?9, rating_distribution=?10, imdb_id=?11, episodes=?12, runtime=?13, countries=?14, languages=?15 where id=?16", params![
+        resource.pic,
+        resource.directors,
+        resource.writers,
+        resource.actors,
+        resource.types,
+        resource.released_at,
+        resource.summary,
+        resource.rating,
+        resource.votes,
+        serde_json::to_string(&resource.rating_distribution)?,
+        resource.imdb_id,
+        resource.episodes,
+        resource.runtime,
+        resource.countries,
+        resource.languages,
+        resource.id
+    ])?;
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::migrate;
+
+    use super::*;
+
+    fn pin(db: &Connection, resource_id: i64, upstream_id: i64, subject_id: &str) {
+        let pinned = Some(("douban", subject_id));
+        save_pin(db, upstream_id, "douban", subject_id).unwrap();
+        let failed: Result<Matched> = Err(anyhow::anyhow!("Timed out"));
+        record(db, resource_id, &failed, pinned).unwrap();
+    }
+
+    #[test]
+    fn pins_of_resources_without_upstream_id_are_not_shared() {
+        let db = Connection::open_in_memory().unwrap();
+        migrate(&db).unwrap();
+        pin(&db, 1, 0, "1292052");
+        assert_eq!(
+            load_pin(&db, 1, 0).unwrap(),
+            Some(("douban".to_string(), "1292052".to_string()))
+        );
+        assert_eq!(load_pin(&db, 2, 0).unwrap(), None);
+        let pins: i64 = db
+            .query_row("select count(*) from metadata_pins", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(pins, 0);
+    }
+
+    #[test]
+    fn pins_follow_the_upstream_id() {
+        let db = Connection::open_in_memory().unwrap();
+        migrate(&db).unwrap();
+        pin(&db, 1, 10733, "3016187");
+        // The same resource under a new id after an import.
+        assert_eq!(
+            load_pin(&db, 7, 10733).unwrap(),
+            Some(("douban".to_string(), "3016187".to_string()))
+        );
+        assert_eq!(load_pin(&db, 2, 10734).unwrap(), None);
+    }
+}
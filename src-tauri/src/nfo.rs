@@ -0,0 +1,199 @@
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use tauri::utils::html;
+
+use crate::{
+    api::Resource,
+    http::Scraper,
+    images,
+    provider::{credits, BoxFuture, Metadata, MetadataProvider, ACTOR, DIRECTOR, WRITER},
+    settings::MetadataSettings,
+};
+
+/// Used for posters the `.nfo` files point to by url.
+static SCRAPER: Lazy<Scraper> = Lazy::new(|| Scraper::new(2.0, 4));
+
+/// Reads Kodi-style `.nfo` files kept under `MetadataSettings::nfo_dir`, as
+/// either `<name>.nfo`, `<name>/tvshow.nfo` or `<name>/movie.nfo`.
+pub struct NfoProvider {
+    dir: PathBuf,
+}
+
+impl NfoProvider {
+    pub fn new(settings: &MetadataSettings) -> Self {
+        NfoProvider {
+            dir: PathBuf::from(settings.nfo_dir.as_str()),
+        }
+    }
+
+    fn find(&self, resource: &Resource) -> Option<PathBuf> {
+        [resource.name.as_str(), resource.original_name.as_str()]
+            .into_iter()
+            .map(|name| name.trim())
+            .filter(|name| !name.is_empty() && !name.contains(['/', '\\']))
+            .flat_map(|name| {
+                [
+                    self.dir.join(format!("{}.nfo", name)),
+                    self.dir.join(name).join("tvshow.nfo"),
+                    self.dir.join(name).join("movie.nfo"),
+                ]
+            })
+            .find(|path| path.is_file())
+    }
+}
+
+/// Parses the `<tvshow>` or `<movie>` document of a `.nfo` file. Relative
+/// thumbs are resolved against the directory of the file.
+pub fn parse(path: &Path) -> Result<Metadata> {
+    let document = html::parse(read_to_string(path)?);
+    let text = |selector: &str| {
+        document
+            .select_first(selector)
+            .map(|el| el.text_contents().trim().to_string())
+            .unwrap_or_default()
+    };
+    let all = |selector: &str| {
+        document
+            .select(selector)
+            .map(|elements| {
+                elements
+                    .map(|el| el.text_contents().trim().to_string())
+                    .filter(|value| !value.is_empty())
+                    .collect::<Vec<String>>()
+            })
+            .unwrap_or_default()
+    };
+    let directors = all("director");
+    let writers = all("credits");
+    let actors = all("actor name");
+    let rating = [
+        "ratings rating[default='true'] value",
+        "ratings rating value",
+        "rating",
+    ]
+    .into_iter()
+    .map(text)
+    .find(|rating| !rating.is_empty())
+    .unwrap_or_default();
+    let votes = [
+        "ratings rating[default='true'] votes",
+        "ratings rating votes",
+        "votes",
+    ]
+    .into_iter()
+    .map(text)
+    .find(|votes| !votes.is_empty())
+    .unwrap_or_default();
+    let mut imdb_id = text("uniqueid[type='imdb']");
+    if imdb_id.is_empty() {
+        imdb_id = text("imdbid");
+    }
+    let mut released_at = text("premiered");
+    if released_at.is_empty() {
+        released_at = text("year");
+    }
+    let mut pic = text("thumb[aspect='poster']");
+    if pic.is_empty() {
+        pic = text("thumb");
+    }
+    if !pic.is_empty() && !pic.starts_with("http") {
+        if let Some(dir) = path.parent() {
+            pic = dir.join(pic).to_string_lossy().to_string();
+        }
+    }
+    Ok(Metadata {
+        subject_id: path.to_string_lossy().to_string(),
+        confidence: 1.0,
+        pic,
+        directors: directors.join("/"),
+        writers: writers.join("/"),
+        actors: actors.join("/"),
+        credits: credits(&[(DIRECTOR, &directors), (WRITER, &writers), (ACTOR, &actors)]),
+        types: all("genre").join("/"),
+        released_at,
+        summary: text("plot"),
+        rating: rating.parse().unwrap_or(0.0),
+        votes: votes.parse().unwrap_or(0),
+        rating_distribution: vec![],
+        imdb_id,
+        episodes: 0,
+        runtime: text("runtime").parse().unwrap_or(0),
+        countries: all("country").join("/"),
+        languages: String::new(),
+        missing: vec![],
+    })
+}
+
+impl MetadataProvider for NfoProvider {
+    fn name(&self) -> &'static str {
+        "nfo"
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        resource: &'a Resource,
+        pinned: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Option<Metadata>>> {
+        Box::pin(async move {
+            let path = match pinned {
+                Some(path) => Some(PathBuf::from(path)),
+                None => self.find(resource),
+            };
+            match path {
+                Some(path) => Ok(Some(parse(&path)?)),
+                None => Ok(None),
+            }
+        })
+    }
+
+    fn image<'a>(&'a self, src: &'a str) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            if !src.starts_with("http") {
+                return images::copy(Path::new(src));
+            }
+            if let Some(filename) = images::cached(src) {
+                return Ok(filename);
+            }
+            images::save(src, SCRAPER.get(src).await?).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/nfo");
+
+    #[test]
+    fn parse_reads_a_tvshow() {
+        let path = Path::new(FIXTURES).join("tvshow.nfo");
+        let metadata = parse(&path).unwrap();
+        assert_eq!(metadata.subject_id, path.to_string_lossy());
+        assert_eq!(metadata.directors, "Timothy Van Patten");
+        assert_eq!(metadata.writers, "David Benioff/D. B. Weiss");
+        assert_eq!(metadata.actors, "Emilia Clarke/Kit Harington");
+        assert_eq!(metadata.credits.len(), 5);
+        assert_eq!(metadata.types, "剧情/奇幻");
+        assert_eq!(metadata.released_at, "2011-04-17");
+        assert_eq!(
+            metadata.summary,
+            "七大王国的贵族家族为争夺铁王座展开了血腥的斗争。"
+        );
+        // The default rating wins over the first one.
+        assert_eq!(metadata.rating, 9.2);
+        assert_eq!(metadata.votes, 2100000);
+        assert_eq!(metadata.imdb_id, "tt0944947");
+        assert_eq!(metadata.runtime, 60);
+        assert_eq!(metadata.countries, "美国");
+        assert_eq!(
+            metadata.pic,
+            Path::new(FIXTURES).join("poster.jpg").to_string_lossy()
+        );
+    }
+}
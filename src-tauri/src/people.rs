@@ -0,0 +1,112 @@
+use anyhow::Result;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use tauri::command;
+
+use crate::{api::SearchResult, database::execute, provider::Credit, response::Response};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Person {
+    pub id: i64,
+    pub name: String,
+    pub douban_id: String,
+    /// Number of resources in the catalog crediting the person.
+    pub resources: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PersonCredit {
+    pub role: String,
+    #[serde(flatten)]
+    pub resource: SearchResult,
+}
+
+/// Replaces the credits of a resource. People are matched by Douban id when
+/// known, by name otherwise.
+pub fn save_credits(db: &mut Connection, resource_id: i64, credits: &[Credit]) -> Result<()> {
+    let tx = db.transaction()?;
+    tx.execute("delete from credits where resource_id=?1", [resource_id])?;
+    {
+        let mut insert_person = tx.prepare_cached(
+            "insert into people (name, douban_id) values (?1, ?2) on conflict (name, douban_id) do nothing",
+        )?;
+        let mut select_person =
+            tx.prepare_cached("select id from people where name=?1 and douban_id=?2")?;
+        let mut select_by_douban_id = tx.prepare_cached(
+            "select id from people where douban_id=?1 and douban_id!='' order by id asc limit 1",
+        )?;
+        let mut insert_credit = tx.prepare_cached(
+            "insert or ignore into credits (resource_id, person_id, role, position) values (?1, ?2, ?3, ?4)",
+        )?;
+        for (position, credit) in credits.iter().enumerate() {
+            let name = credit.name.trim();
+            if name.is_empty() {
+                continue;
+            }
+            let existing: Option<i64> = select_by_douban_id
+                .query_map([credit.douban_id.as_str()], |row| row.get(0))?
+                .next()
+                .transpose()?;
+            let person_id = match existing {
+                Some(person_id) => person_id,
+                None => {
+                    insert_person.execute(params![name, credit.douban_id])?;
+                    select_person.query_row(params![name, credit.douban_id], |row| row.get(0))?
+                }
+            };
+            insert_credit.execute(params![resource_id, person_id, credit.role, position])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+#[command]
+pub async fn search_people(keyword: String) -> Response<Vec<Person>> {
+    let result = execute(|db| {
+        let mut stmt = db.prepare("select p.id, p.name, p.douban_id, count(distinct c.resource_id) as resources from people as p inner join credits as c on c.person_id=p.id where p.name like ?1 group by p.id order by resources desc, p.id asc")?;
+        let mut rows = stmt.query(params![format!("%{}%", keyword)])?;
+        let mut people = vec![];
+        while let Some(row) = rows.next()? {
+            people.push(Person {
+                id: row.get("id")?,
+                name: row.get("name")?,
+                douban_id: row.get("douban_id")?,
+                resources: row.get("resources")?,
+            });
+        }
+        Ok(people)
+    });
+    match result {
+        Ok(people) => Response::ok("success", people),
+        Err(e) => Response::fail(e.to_string().as_str(), Vec::new()),
+    }
+}
+
+/// Everything in the catalog the person is credited on, optionally limited to
+/// one role such as `actor` or `director`.
+#[command]
+pub async fn person_resources(id: i64, role: Option<String>) -> Response<Vec<PersonCredit>> {
+    let result = execute(|db| {
+        let mut stmt = db.prepare("select c.role, r.id, r.name, r.original_name, r.alias_name, ch.name as channel from credits as c inner join resources as r on r.id=c.resource_id left join channels as ch on ch.id=r.channel_id where c.person_id=?1 and (?2 is null or c.role=?2) order by r.released_at desc, r.id desc")?;
+        let mut rows = stmt.query(params![id, role])?;
+        let mut credits = vec![];
+        while let Some(row) = rows.next()? {
+            credits.push(PersonCredit {
+                role: row.get("role")?,
+                resource: SearchResult {
+                    id: row.get("id")?,
+                    name: row.get("name")?,
+                    original_name: row.get("original_name")?,
+                    alias_name: row.get("alias_name")?,
+                    channel: row.get("channel")?,
+                },
+            });
+        }
+        Ok(credits)
+    });
+    match result {
+        Ok(credits) => Response::ok("success", credits),
+        Err(e) => Response::fail(e.to_string().as_str(), Vec::new()),
+    }
+}
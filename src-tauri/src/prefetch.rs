@@ -0,0 +1,250 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+};
+
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tauri::{async_runtime, command, AppHandle, Manager, State};
+
+use crate::{
+    api::load_resource, database::execute, initialize::CancellationToken, metadata,
+    response::Response, status::now,
+};
+
+/// Resources looked up on Douban at the same time.
+const WORKERS: usize = 4;
+
+/// Background job enriching queued resources with Douban metadata. The queue
+/// lives in the `metadata_queue` table so it survives a restart.
+#[derive(Clone, Default)]
+pub struct Prefetcher {
+    running: Arc<AtomicBool>,
+    token: CancellationToken,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PrefetchProgress {
+    pub done: u64,
+    pub failed: u64,
+    pub total: u64,
+    /// The resource that was just processed.
+    pub resource_id: Option<i64>,
+}
+
+#[derive(Default)]
+struct Counters {
+    done: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl Prefetcher {
+    /// Starts draining the queue unless a job is already running.
+    pub fn start(&self, app_handle: AppHandle) {
+        if self.running.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.token.reset();
+        let prefetcher = self.clone();
+        async_runtime::spawn(async move {
+            prefetcher.process(&app_handle).await;
+            prefetcher.running.store(false, Ordering::SeqCst);
+            // Resources queued after the workers ran dry, or after a cancel
+            // while they were finishing, which `start` then ignored.
+            if pending().unwrap_or(0) > 0 {
+                prefetcher.start(app_handle);
+            }
+        });
+    }
+
+    pub fn cancel(&self) -> Result<()> {
+        self.token.cancel();
+        execute(|db| {
+            db.execute("delete from metadata_queue", [])?;
+            Ok(())
+        })
+    }
+
+    async fn process(&self, app_handle: &AppHandle) {
+        let counters = Arc::new(Counters::default());
+        let mut handles = vec![];
+        for _ in 0..WORKERS {
+            let app_handle = app_handle.clone();
+            let token = self.token.clone();
+            let counters = counters.clone();
+            handles.push(async_runtime::spawn(async move {
+                work(&app_handle, &token, &counters).await
+            }));
+        }
+        for handle in handles {
+            let _ = handle.await;
+        }
+        let step = if self.token.is_cancelled() {
+            "cancelled"
+        } else {
+            "finish"
+        };
+        emit_prefetch_event(app_handle, step, progress(&counters, None));
+    }
+}
+
+async fn work(app_handle: &AppHandle, token: &CancellationToken, counters: &Counters) {
+    while !token.is_cancelled() {
+        let resource_id = match execute(claim) {
+            Ok(Some(resource_id)) => resource_id,
+            _ => break,
+        };
+        match enrich(resource_id).await {
+            Ok(()) => counters.done.fetch_add(1, Ordering::SeqCst),
+            Err(_) => counters.failed.fetch_add(1, Ordering::SeqCst),
+        };
+        let _ = execute(|db| {
+            db.execute(
+                "delete from metadata_queue where resource_id=?1",
+                [resource_id],
+            )?;
+            Ok(())
+        });
+        emit_prefetch_event(
+            app_handle,
+            "progress",
+            progress(counters, Some(resource_id)),
+        );
+    }
+}
+
+async fn enrich(resource_id: i64) -> Result<()> {
+    // Already being looked up for a window showing it.
+    if !metadata::start_enriching(resource_id) {
+        return Ok(());
+    }
+    let result = async {
+        let mut resource = execute(|db| load_resource(db, resource_id))?;
+        if metadata::should_fetch(&resource) {
+            metadata::fetch(&mut resource).await?;
+        }
+        Ok(())
+    }
+    .await;
+    metadata::finish_enriching(resource_id);
+    result
+}
+
+/// Marks the oldest pending resource as running and returns it.
+fn claim(db: &mut Connection) -> Result<Option<i64>> {
+    let resource_id = db
+        .query_row(
+            "update metadata_queue set status='running' where resource_id=(select resource_id from metadata_queue where status='pending' order by queued_at asc, resource_id asc limit 1) returning resource_id",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(resource_id)
+}
+
+fn pending() -> Result<u64> {
+    execute(|db| {
+        let count = db.query_row(
+            "select count(*) from metadata_queue where status='pending'",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    })
+}
+
+fn progress(counters: &Counters, resource_id: Option<i64>) -> PrefetchProgress {
+    let done = counters.done.load(Ordering::SeqCst);
+    let failed = counters.failed.load(Ordering::SeqCst);
+    let remaining = execute(|db| {
+        let count: u64 =
+            db.query_row("select count(*) from metadata_queue", [], |row| row.get(0))?;
+        Ok(count)
+    })
+    .unwrap_or(0);
+    PrefetchProgress {
+        done,
+        failed,
+        total: done + failed + remaining,
+        resource_id,
+    }
+}
+
+fn emit_prefetch_event(app_handle: &AppHandle, step: &str, progress: PrefetchProgress) {
+    let _ = app_handle.emit_all("rubick_prefetch", Response::ok(step, progress));
+}
+
+fn enqueue(db: &mut Connection, resource_ids: &[i64]) -> Result<usize> {
+    let tx = db.transaction()?;
+    let mut size = 0;
+    {
+        let mut stmt = tx.prepare(
+            "insert or ignore into metadata_queue (resource_id, status, queued_at) values (?1, 'pending', ?2)",
+        )?;
+        let queued_at = now();
+        for resource_id in resource_ids {
+            size += stmt.execute(params![resource_id, queued_at])?;
+        }
+    }
+    tx.commit()?;
+    Ok(size)
+}
+
+/// Continues a queue left over from the previous run.
+pub fn resume(app_handle: AppHandle) {
+    let _ = execute(|db| {
+        db.execute(
+            "update metadata_queue set status='pending' where status='running'",
+            [],
+        )?;
+        Ok(())
+    });
+    if pending().unwrap_or(0) > 0 {
+        app_handle.state::<Prefetcher>().start(app_handle.clone());
+    }
+}
+
+#[command]
+pub fn prefetch_favorites(
+    app_handle: AppHandle,
+    prefetcher: State<'_, Prefetcher>,
+) -> Response<usize> {
+    let result = execute(|db| {
+        let resource_ids = {
+            let mut stmt = db.prepare("select resource_id from favorites")?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<Vec<i64>>>()?
+        };
+        enqueue(db, &resource_ids)
+    });
+    start(app_handle, &prefetcher, result)
+}
+
+#[command]
+pub fn prefetch_resources(
+    ids: Vec<i64>,
+    app_handle: AppHandle,
+    prefetcher: State<'_, Prefetcher>,
+) -> Response<usize> {
+    let result = execute(|db| enqueue(db, &ids));
+    start(app_handle, &prefetcher, result)
+}
+
+fn start(app_handle: AppHandle, prefetcher: &Prefetcher, queued: Result<usize>) -> Response<usize> {
+    match queued {
+        Ok(size) => {
+            prefetcher.start(app_handle);
+            Response::ok("success", size)
+        }
+        Err(e) => Response::fail(e.to_string().as_str(), 0),
+    }
+}
+
+#[command]
+pub fn cancel_prefetch(prefetcher: State<'_, Prefetcher>) -> Response<()> {
+    match prefetcher.cancel() {
+        Ok(()) => Response::ok("success", ()),
+        Err(e) => Response::fail(e.to_string().as_str(), ()),
+    }
+}
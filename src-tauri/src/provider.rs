@@ -0,0 +1,154 @@
+use std::{future::Future, pin::Pin};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::Resource, douban::DoubanProvider, nfo::NfoProvider, settings::MetadataSettings,
+    tmdb::TmdbProvider,
+};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+pub const DIRECTOR: &str = "director";
+pub const WRITER: &str = "writer";
+pub const ACTOR: &str = "actor";
+
+/// A person credited on a resource.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Credit {
+    pub name: String,
+    /// `director`, `writer` or `actor`.
+    pub role: String,
+    /// Empty when the provider does not know the person on Douban.
+    pub douban_id: String,
+}
+
+/// Metadata of a resource as found by one provider. Empty strings and zero
+/// numbers mean the provider does not know the field.
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    /// Id of the matched subject on the provider's side.
+    pub subject_id: String,
+    /// How well the subject matched the resource, between 0 and 1.
+    pub confidence: f64,
+    /// Url or path of the poster, passed to `MetadataProvider::image`.
+    pub pic: String,
+    pub directors: String,
+    pub writers: String,
+    pub actors: String,
+    pub credits: Vec<Credit>,
+    pub types: String,
+    pub released_at: String,
+    pub summary: String,
+    pub rating: f64,
+    pub votes: i64,
+    /// Share of votes per star, from five stars down to one, in percent.
+    pub rating_distribution: Vec<f64>,
+    pub imdb_id: String,
+    pub episodes: i64,
+    /// In minutes, of an episode for series.
+    pub runtime: i64,
+    pub countries: String,
+    pub languages: String,
+    /// Fields the provider expected but could not parse.
+    pub missing: Vec<String>,
+}
+
+impl Metadata {
+    /// Fills the fields still unknown from `other`.
+    pub fn merge(&mut self, other: &Metadata) {
+        let fields = [
+            (&mut self.directors, &other.directors),
+            (&mut self.writers, &other.writers),
+            (&mut self.actors, &other.actors),
+            (&mut self.types, &other.types),
+            (&mut self.released_at, &other.released_at),
+            (&mut self.summary, &other.summary),
+            (&mut self.imdb_id, &other.imdb_id),
+            (&mut self.countries, &other.countries),
+            (&mut self.languages, &other.languages),
+        ];
+        for (field, value) in fields {
+            if field.trim().is_empty() {
+                *field = value.clone();
+            }
+        }
+        // Votes and their distribution only make sense with the rating.
+        if self.rating <= 0.0 {
+            self.rating = other.rating;
+            self.votes = other.votes;
+            self.rating_distribution = other.rating_distribution.clone();
+        }
+        for (field, value) in [
+            (&mut self.episodes, other.episodes),
+            (&mut self.runtime, other.runtime),
+        ] {
+            if *field <= 0 {
+                *field = value;
+            }
+        }
+        for role in [DIRECTOR, WRITER, ACTOR] {
+            if !self.credits.iter().any(|credit| credit.role == role) {
+                self.credits.extend(
+                    other
+                        .credits
+                        .iter()
+                        .filter(|credit| credit.role == role)
+                        .cloned(),
+                );
+            }
+        }
+    }
+}
+
+/// Credits for providers that only know names.
+pub fn credits(roles: &[(&str, &Vec<String>)]) -> Vec<Credit> {
+    let mut credits = vec![];
+    for (role, names) in roles {
+        for name in names.iter() {
+            credits.push(Credit {
+                name: name.to_string(),
+                role: role.to_string(),
+                douban_id: String::new(),
+            });
+        }
+    }
+    credits
+}
+
+pub trait MetadataProvider: Send + Sync {
+    /// Key of the provider in `MetadataSettings::providers`.
+    fn name(&self) -> &'static str;
+
+    /// Looks `resource` up. `pinned` is a subject id of this provider that the
+    /// user linked by hand, to be used instead of searching.
+    fn lookup<'a>(
+        &'a self,
+        resource: &'a Resource,
+        pinned: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Option<Metadata>>>;
+
+    /// Stores the poster found by `lookup` in the image dir and returns its
+    /// file name.
+    fn image<'a>(&'a self, src: &'a str) -> BoxFuture<'a, Result<String>>;
+}
+
+/// Builds the provider called `name`, if it is known and configured.
+pub fn provider(name: &str, settings: &MetadataSettings) -> Option<Box<dyn MetadataProvider>> {
+    match name {
+        "douban" => Some(Box::new(DoubanProvider)),
+        "tmdb" if !settings.tmdb_api_key.is_empty() => Some(Box::new(TmdbProvider::new(settings))),
+        "nfo" if !settings.nfo_dir.is_empty() => Some(Box::new(NfoProvider::new(settings))),
+        _ => None,
+    }
+}
+
+/// The enabled providers, in the configured order.
+pub fn providers(settings: &MetadataSettings) -> Vec<Box<dyn MetadataProvider>> {
+    settings
+        .providers
+        .iter()
+        .filter_map(|name| provider(name, settings))
+        .collect()
+}
@@ -0,0 +1,160 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Debug, Formatter},
+    fs::{read_to_string, write},
+    path::PathBuf,
+};
+use tauri::command;
+
+use crate::{application::app_dir, douban, http, response::Response, tmdb, yyets::DEFAULT_MIRROR};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mirror {
+    pub url: String,
+    /// Seconds to wait for the connection and for every chunk of the download.
+    pub timeout: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetadataSettings {
+    /// Providers asked for metadata, in order. Fields the first one leaves
+    /// empty are filled by the next.
+    pub providers: Vec<String>,
+    /// TMDB is skipped while this is empty.
+    pub tmdb_api_key: String,
+    pub tmdb_base_url: String,
+    pub tmdb_language: String,
+    /// Directory of Kodi-style `.nfo` files, skipped while empty.
+    pub nfo_dir: String,
+    /// `Cookie` header sent to Douban, set through `import_douban_cookies`.
+    /// A logged-in session is throttled much later than anonymous scraping.
+    pub douban_cookie: String,
+}
+
+/// Leaves the API key and the cookie out, so they never end up in a log.
+impl Debug for MetadataSettings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetadataSettings")
+            .field("providers", &self.providers)
+            .field("tmdb_base_url", &self.tmdb_base_url)
+            .field("tmdb_language", &self.tmdb_language)
+            .field("nfo_dir", &self.nfo_dir)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for MetadataSettings {
+    fn default() -> Self {
+        MetadataSettings {
+            providers: vec!["nfo".to_string(), "douban".to_string(), "tmdb".to_string()],
+            tmdb_api_key: String::new(),
+            tmdb_base_url: tmdb::DEFAULT_BASE_URL.to_string(),
+            tmdb_language: "zh-CN".to_string(),
+            nfo_dir: String::new(),
+            douban_cookie: String::new(),
+        }
+    }
+}
+
+/// Proxy every outbound request goes through.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProxySettings {
+    /// E.g. `http://10.0.0.1:8080` or `socks5://10.0.0.1:1080`. No proxy is
+    /// used while this is empty.
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    /// Comma separated hosts, domains and IP ranges reached directly, in the
+    /// format of the `NO_PROXY` environment variable.
+    pub no_proxy: String,
+}
+
+/// Leaves the password out, so it never ends up in a log.
+impl Debug for ProxySettings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxySettings")
+            .field("url", &self.url)
+            .field("username", &self.username)
+            .field("no_proxy", &self.no_proxy)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Download mirrors of the yyets dump, tried in order.
+    pub mirrors: Vec<Mirror>,
+    pub metadata: MetadataSettings,
+    /// Size cap of the image cache in MiB, 0 for no limit.
+    pub image_cache_size: u64,
+    pub proxy: ProxySettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            mirrors: vec![Mirror {
+                url: DEFAULT_MIRROR.to_string(),
+                timeout: 30,
+            }],
+            metadata: MetadataSettings::default(),
+            image_cache_size: 512,
+            proxy: ProxySettings::default(),
+        }
+    }
+}
+
+pub fn path() -> PathBuf {
+    app_dir().join("settings.json")
+}
+
+pub fn load() -> Settings {
+    // Tests never pick up the proxy or the keys of whoever runs them.
+    if cfg!(test) {
+        return Settings::default();
+    }
+    read_to_string(path())
+        .ok()
+        .and_then(|contents| serde_json::from_str::<Settings>(contents.as_str()).ok())
+        .unwrap_or_default()
+}
+
+pub fn save(settings: &Settings) -> Result<()> {
+    write(path(), serde_json::to_string_pretty(settings)?)?;
+    http::reload();
+    Ok(())
+}
+
+#[command]
+pub async fn settings() -> Response<Settings> {
+    Response::ok("success", load())
+}
+
+#[command]
+pub async fn save_settings(settings: Settings) -> Response<()> {
+    match save(&settings) {
+        Ok(()) => Response::ok("success", ()),
+        Err(e) => Response::fail(e.to_string().as_str(), ()),
+    }
+}
+
+/// Imports Douban cookies from a `Cookie` header value or the contents of a
+/// Netscape cookies.txt, replacing the stored ones. Returns how many cookies
+/// were imported.
+#[command]
+pub async fn import_douban_cookies(cookies: String) -> Response<usize> {
+    let result = douban::parse_cookies(cookies.as_str()).and_then(|(cookie, count)| {
+        let mut settings = load();
+        settings.metadata.douban_cookie = cookie;
+        save(&settings)?;
+        Ok(count)
+    });
+    match result {
+        Ok(count) => Response::ok("success", count),
+        Err(e) => Response::fail(e.to_string().as_str(), 0),
+    }
+}
@@ -0,0 +1,129 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Lines},
+    path::Path,
+};
+
+/// A record read from a data source: its upstream id and its raw payload.
+pub type RawRecord = (i64, Result<String>);
+
+/// Turns a raw payload into a normalized resource. Parsers run on worker
+/// threads, so they must not borrow the source.
+pub type Parser = fn(&str) -> Result<Resource>;
+
+/// A catalog that can be imported through `initialize::import_series`.
+pub trait DataSource {
+    /// Number of records, used to report progress.
+    fn total(&self) -> Result<i32>;
+
+    /// Reads up to `size` raw records, returning an empty batch once exhausted.
+    fn read(&mut self, size: usize) -> Result<Vec<RawRecord>>;
+
+    fn parser(&self) -> Parser;
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Resource {
+    pub upstream_id: i64,
+    pub name: String,
+    pub original_name: String,
+    pub alias_name: String,
+    pub channel: String,
+    pub channel_cn: String,
+    pub area: String,
+    pub show_type: String,
+    pub expire: String,
+    pub views: i64,
+    pub year: String,
+    pub seasons: Vec<Season>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Season {
+    pub season: String,
+    pub name: String,
+    pub formats: Vec<Format>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Format {
+    pub format: String,
+    pub episodes: Vec<Episode>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Episode {
+    pub upstream_id: String,
+    pub episode: String,
+    pub name: String,
+    pub size: String,
+    pub dateline: String,
+    pub yyets_trans: i64,
+    pub links: Vec<Link>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Link {
+    /// Short code of the download method, e.g. `1` for ed2k in yyets dumps.
+    pub way: String,
+    /// Display name of the download method, used as the `ways` lookup key.
+    pub way_cn: String,
+    pub address: String,
+    pub password: String,
+}
+
+/// Reads one JSON encoded `Resource` per line, skipping blank lines. The line
+/// number is used as the upstream id.
+pub struct JsonLinesSource {
+    total: i32,
+    line: i64,
+    lines: Lines<BufReader<File>>,
+}
+
+impl JsonLinesSource {
+    pub fn open(path: &Path) -> Result<Self> {
+        let total = BufReader::new(File::open(path)?)
+            .lines()
+            .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+            .count() as i32;
+        Ok(JsonLinesSource {
+            total,
+            line: 0,
+            lines: BufReader::new(File::open(path)?).lines(),
+        })
+    }
+}
+
+impl DataSource for JsonLinesSource {
+    fn total(&self) -> Result<i32> {
+        Ok(self.total)
+    }
+
+    fn read(&mut self, size: usize) -> Result<Vec<RawRecord>> {
+        let mut batch = Vec::with_capacity(size);
+        while batch.len() < size {
+            match self.lines.next() {
+                Some(line) => {
+                    self.line += 1;
+                    match line {
+                        Ok(line) if line.trim().is_empty() => {}
+                        line => batch.push((self.line, line.map_err(anyhow::Error::from))),
+                    }
+                }
+                None => break,
+            }
+        }
+        Ok(batch)
+    }
+
+    fn parser(&self) -> Parser {
+        |data| Ok(serde_json::from_str::<Resource>(data)?)
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::Result;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::time;
+
+use crate::database::execute;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InitializeStatus {
+    NotStarted,
+    Downloading,
+    Downloaded,
+    Importing,
+    Complete,
+    Failed,
+}
+
+impl InitializeStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InitializeStatus::NotStarted => "not_started",
+            InitializeStatus::Downloading => "downloading",
+            InitializeStatus::Downloaded => "downloaded",
+            InitializeStatus::Importing => "importing",
+            InitializeStatus::Complete => "complete",
+            InitializeStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(status: &str) -> Self {
+        match status {
+            "downloading" => InitializeStatus::Downloading,
+            "downloaded" => InitializeStatus::Downloaded,
+            "importing" => InitializeStatus::Importing,
+            "complete" => InitializeStatus::Complete,
+            "failed" => InitializeStatus::Failed,
+            _ => InitializeStatus::NotStarted,
+        }
+    }
+}
+
+/// The persisted progress of the catalog initialization.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InitializeState {
+    pub status: InitializeStatus,
+    /// Mirror the dump was downloaded from.
+    pub source: String,
+    /// Path of the extracted dump, kept so an interrupted import can resume.
+    pub dump: String,
+    pub error: String,
+    pub started_at: i64,
+    pub updated_at: i64,
+}
+
+impl Default for InitializeState {
+    fn default() -> Self {
+        InitializeState {
+            status: InitializeStatus::NotStarted,
+            source: String::new(),
+            dump: String::new(),
+            error: String::new(),
+            started_at: 0,
+            updated_at: 0,
+        }
+    }
+}
+
+pub fn now() -> i64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+pub fn load() -> Result<Option<InitializeState>> {
+    execute(|connection| {
+        let state = connection
+            .query_row("select * from initialization where id=1", [], |row| {
+                Ok(InitializeState {
+                    status: InitializeStatus::parse(row.get::<&str, String>("status")?.as_str()),
+                    source: row.get("source")?,
+                    dump: row.get("dump")?,
+                    error: row.get("error")?,
+                    started_at: row.get("started_at")?,
+                    updated_at: row.get("updated_at")?,
+                })
+            })
+            .optional()?;
+        Ok(state)
+    })
+}
+
+pub fn current() -> InitializeState {
+    load().ok().flatten().unwrap_or_default()
+}
+
+pub fn save(state: &InitializeState) -> Result<()> {
+    execute(|connection| {
+        connection.execute(
+            "insert or replace into initialization (id, status, source, dump, error, started_at, updated_at) values (1, ?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                state.status.as_str(),
+                state.source,
+                state.dump,
+                state.error,
+                state.started_at,
+                state.updated_at
+            ],
+        )?;
+        Ok(())
+    })
+}
+
+/// Applies `f` to the stored state and persists the result.
+pub fn update<F: FnOnce(&mut InitializeState)>(f: F) -> Result<InitializeState> {
+    let mut state = current();
+    f(&mut state);
+    state.updated_at = now();
+    save(&state)?;
+    Ok(state)
+}
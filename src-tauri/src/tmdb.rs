@@ -0,0 +1,383 @@
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use reqwest::Url;
+use serde_json::Value;
+
+use crate::{
+    api::Resource,
+    http::{self, Scraper},
+    images,
+    matching::{best_match, Candidate, Query},
+    provider::{credits, BoxFuture, Metadata, MetadataProvider, ACTOR, DIRECTOR, WRITER},
+    settings::MetadataSettings,
+};
+
+pub const DEFAULT_BASE_URL: &str = "https://api.themoviedb.org/3";
+const IMAGE_BASE_URL: &str = "https://image.tmdb.org/t/p/w500";
+/// Cast members kept from the credits.
+const MAX_ACTORS: usize = 15;
+
+/// TMDB allows about forty requests every ten seconds.
+static SCRAPER: Lazy<Scraper> = Lazy::new(|| Scraper::new(3.0, 10));
+
+/// Looks resources up through the TMDB v3 API. Subject ids are stored as
+/// `<media>/<id>`, e.g. `tv/1399`.
+pub struct TmdbProvider {
+    api_key: String,
+    base_url: String,
+    language: String,
+}
+
+impl TmdbProvider {
+    pub fn new(settings: &MetadataSettings) -> Self {
+        TmdbProvider {
+            api_key: settings.tmdb_api_key.to_string(),
+            base_url: settings.tmdb_base_url.trim_end_matches('/').to_string(),
+            language: settings.tmdb_language.to_string(),
+        }
+    }
+
+    async fn get(&self, path: &str, params: &[(&str, &str)]) -> Result<Value> {
+        let mut query = vec![
+            ("api_key", self.api_key.as_str()),
+            ("language", self.language.as_str()),
+        ];
+        query.extend_from_slice(params);
+        let url = Url::parse_with_params(format!("{}{}", self.base_url, path).as_str(), query)?;
+        let response = SCRAPER.get(url).await?;
+        Ok(serde_json::from_str(http::text(response).await?.as_str())?)
+    }
+
+    async fn search(&self, media: &str, query: &Query<'_>) -> Result<Vec<Candidate>> {
+        let year = query.year.split('/').next().unwrap_or_default().trim();
+        let year_param = if media == "tv" {
+            "first_air_date_year"
+        } else {
+            "year"
+        };
+        let mut params = vec![("query", query.name)];
+        if !year.is_empty() {
+            params.push((year_param, year));
+        }
+        let body = self
+            .get(format!("/search/{}", media).as_str(), &params)
+            .await?;
+        let results = body["results"].as_array().cloned().unwrap_or_default();
+        Ok(results
+            .iter()
+            .filter_map(|result| {
+                Some(Candidate {
+                    id: format!("{}/{}", media, result["id"].as_i64()?),
+                    title: string(&result[title_key(media)]),
+                    original_title: string(&result[format!("original_{}", title_key(media))]),
+                    year: string(&result[date_key(media)])
+                        .get(0..4)
+                        .and_then(|year| year.parse().ok()),
+                    category: String::new(),
+                })
+            })
+            .collect())
+    }
+
+    async fn details(&self, subject_id: &str) -> Result<Metadata> {
+        let media = subject_id.split('/').next().unwrap_or("tv");
+        let body = self
+            .get(
+                format!("/{}", subject_id).as_str(),
+                &[("append_to_response", "credits,external_ids")],
+            )
+            .await?;
+        let crew = body["credits"]["crew"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        let jobs = |jobs: &[&str]| {
+            let mut names: Vec<String> = vec![];
+            for member in crew.iter() {
+                let name = string(&member["name"]);
+                if jobs.contains(&member["job"].as_str().unwrap_or_default())
+                    && !names.contains(&name)
+                {
+                    names.push(name);
+                }
+            }
+            names
+        };
+        let mut directors = jobs(&["Director"]);
+        if directors.is_empty() {
+            directors = names(&body["created_by"], usize::MAX);
+        }
+        let writers = jobs(&["Writer", "Screenplay", "Story"]);
+        let actors = names(&body["credits"]["cast"], MAX_ACTORS);
+        let imdb_id = [&body["imdb_id"], &body["external_ids"]["imdb_id"]]
+            .into_iter()
+            .map(string)
+            .find(|id| !id.is_empty())
+            .unwrap_or_default();
+        // Series only have the runtimes of their episodes.
+        let runtime = body["runtime"]
+            .as_i64()
+            .or_else(|| body["episode_run_time"][0].as_i64())
+            .unwrap_or(0);
+        Ok(Metadata {
+            subject_id: subject_id.to_string(),
+            confidence: 1.0,
+            pic: body["poster_path"]
+                .as_str()
+                .map(|path| format!("{}{}", IMAGE_BASE_URL, path))
+                .unwrap_or_default(),
+            directors: directors.join("/"),
+            writers: writers.join("/"),
+            actors: actors.join("/"),
+            credits: credits(&[(DIRECTOR, &directors), (WRITER, &writers), (ACTOR, &actors)]),
+            types: names(&body["genres"], usize::MAX).join("/"),
+            released_at: string(&body[date_key(media)]),
+            summary: string(&body["overview"]),
+            rating: body["vote_average"].as_f64().unwrap_or(0.0),
+            votes: body["vote_count"].as_i64().unwrap_or(0),
+            rating_distribution: vec![],
+            imdb_id,
+            episodes: body["number_of_episodes"].as_i64().unwrap_or(0),
+            runtime,
+            countries: names(&body["production_countries"], usize::MAX).join("/"),
+            languages: names(&body["spoken_languages"], usize::MAX).join("/"),
+            missing: vec![],
+        })
+    }
+}
+
+fn title_key(media: &str) -> &'static str {
+    if media == "tv" {
+        "name"
+    } else {
+        "title"
+    }
+}
+
+fn date_key(media: &str) -> &'static str {
+    if media == "tv" {
+        "first_air_date"
+    } else {
+        "release_date"
+    }
+}
+
+fn string(value: &Value) -> String {
+    value.as_str().unwrap_or_default().trim().to_string()
+}
+
+fn names(values: &Value, limit: usize) -> Vec<String> {
+    values
+        .as_array()
+        .map(|values| {
+            values
+                .iter()
+                .map(|value| string(&value["name"]))
+                .filter(|name| !name.is_empty())
+                .take(limit)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+impl MetadataProvider for TmdbProvider {
+    fn name(&self) -> &'static str {
+        "tmdb"
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        resource: &'a Resource,
+        pinned: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Option<Metadata>>> {
+        Box::pin(async move {
+            if let Some(subject_id) = pinned {
+                return Ok(Some(self.details(subject_id).await?));
+            }
+            let query = Query::from(resource);
+            let media = if query.channel == "movie" {
+                "movie"
+            } else {
+                "tv"
+            };
+            let candidates = self.search(media, &query).await?;
+            match best_match(candidates, &query) {
+                Some((candidate, confidence)) => {
+                    let mut metadata = self.details(candidate.id.as_str()).await?;
+                    metadata.confidence = confidence;
+                    Ok(Some(metadata))
+                }
+                None => Ok(None),
+            }
+        })
+    }
+
+    fn image<'a>(&'a self, src: &'a str) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            if let Some(filename) = images::cached(src) {
+                return Ok(filename);
+            }
+            images::save(src, SCRAPER.get(src).await?).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::read_to_string,
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use super::*;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tmdb");
+    const API_KEY: &str = "test-api-key";
+
+    /// Answers `/search/tv` with `search_tv.json`, `/tv/1399` with
+    /// `tv_1399.json` and so on, or with a 401 when the fixture is missing
+    /// or the api key was not sent.
+    fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+                let target = request.split_whitespace().nth(1).unwrap_or_default();
+                let (path, query) = target.split_once('?').unwrap_or((target, ""));
+                let fixture = format!("{}/{}.json", FIXTURES, path[1..].replace('/', "_"));
+                let (status, body) = match read_to_string(fixture) {
+                    Ok(body) if query.contains(format!("api_key={}", API_KEY).as_str()) => {
+                        ("200 OK", body)
+                    }
+                    _ => ("401 Unauthorized", r#"{"status_code":7}"#.to_string()),
+                };
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    fn provider(base_url: String) -> TmdbProvider {
+        TmdbProvider::new(&MetadataSettings {
+            tmdb_api_key: API_KEY.to_string(),
+            tmdb_base_url: base_url,
+            ..MetadataSettings::default()
+        })
+    }
+
+    fn resource() -> Resource {
+        Resource {
+            id: 1,
+            name: "权力的游戏".to_string(),
+            original_name: "Game of Thrones".to_string(),
+            alias_name: String::new(),
+            pic: String::new(),
+            pic_list: String::new(),
+            pic_detail: String::new(),
+            directors: String::new(),
+            writers: String::new(),
+            actors: String::new(),
+            types: String::new(),
+            released_at: String::new(),
+            summary: String::new(),
+            rating: 0.0,
+            votes: 0,
+            rating_distribution: vec![],
+            imdb_id: String::new(),
+            episodes: 0,
+            runtime: 0,
+            countries: String::new(),
+            languages: String::new(),
+            seasons: vec![],
+            channel: "tv".to_string(),
+            channel_cn: "美剧".to_string(),
+            area: "美国".to_string(),
+            favorite: false,
+            upstream_id: 10733,
+            show_type: String::new(),
+            expire: String::new(),
+            views: 0,
+            year: "2011".to_string(),
+        }
+    }
+
+    #[async_std::test]
+    async fn lookup_picks_the_best_match() {
+        let metadata = provider(serve())
+            .lookup(&resource(), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(metadata.subject_id, "tv/1399");
+        // Exact title and year, no category from TMDB.
+        assert!((metadata.confidence - 0.925).abs() < 1e-9);
+        assert_eq!(metadata.imdb_id, "tt0944947");
+        assert_eq!(metadata.runtime, 60);
+        assert_eq!(metadata.episodes, 73);
+        assert_eq!(metadata.directors, "David Benioff/D. B. Weiss");
+        assert_eq!(metadata.writers, "David Benioff/D. B. Weiss");
+        assert_eq!(
+            metadata.actors,
+            "Emilia Clarke/Kit Harington/Peter Dinklage"
+        );
+        let credits: Vec<(&str, &str)> = metadata
+            .credits
+            .iter()
+            .map(|credit| (credit.role.as_str(), credit.name.as_str()))
+            .collect();
+        assert_eq!(
+            credits,
+            vec![
+                (DIRECTOR, "David Benioff"),
+                (DIRECTOR, "D. B. Weiss"),
+                (WRITER, "David Benioff"),
+                (WRITER, "D. B. Weiss"),
+                (ACTOR, "Emilia Clarke"),
+                (ACTOR, "Kit Harington"),
+                (ACTOR, "Peter Dinklage"),
+            ]
+        );
+        assert_eq!(
+            metadata.pic,
+            "https://image.tmdb.org/t/p/w500/1XS1oqL89opfnbLl8WnZY1O1uJx.jpg"
+        );
+    }
+
+    #[async_std::test]
+    async fn lookup_of_a_pinned_subject_skips_the_search() {
+        let metadata = provider(serve())
+            .lookup(&resource(), Some("tv/1399"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(metadata.subject_id, "tv/1399");
+        assert_eq!(metadata.confidence, 1.0);
+    }
+
+    #[async_std::test]
+    async fn errors_leave_the_api_key_out() {
+        let error = provider(format!("{}/missing", serve()))
+            .lookup(&resource(), None)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("401"), "{}", error);
+        assert!(!error.contains(API_KEY), "{}", error);
+    }
+}
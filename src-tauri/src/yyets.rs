@@ -5,21 +5,30 @@ use std::{
     collections::{hash_map::DefaultHasher, HashMap},
     error::Error,
     fmt,
-    fs::File,
+    fs::{read_dir, remove_file, File},
     hash::Hasher,
     io::{copy, Cursor, Read, Seek, Write},
-    path::PathBuf,
-    time,
+    path::{Path, PathBuf},
+    time::{self, Duration},
 };
-use tauri::{api::path, Window};
+use rusqlite::{params, Connection};
+use tauri::api::path;
 use zip::ZipArchive;
 
 use crate::{
-    initialize::{emit_initialize_event, ResponseData},
+    database::connect,
+    http,
+    initialize::{
+        emit_initialize_event, CancellationToken, ProgressThrottle, Reporter, ResponseData,
+    },
     response::Response,
+    settings::{self, Mirror},
+    source::{self, DataSource, Parser, RawRecord},
+    status,
 };
 
-const DOWNLOAD_URL: &str = "https://yyets.dmesg.app/dump/yyets_sqlite.zip";
+pub const DEFAULT_MIRROR: &str = "https://yyets.dmesg.app/dump/yyets_sqlite.zip";
+const TMP_PREFIX: &str = "rubick-";
 
 struct YYeTsError {
     error: Box<dyn Error + Send + Sync>,
@@ -51,6 +60,14 @@ impl Error for YYeTsError {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MirrorAttempt {
+    pub url: String,
+    pub index: usize,
+    pub total: usize,
+    pub error: Option<String>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Info {
     pub id: i32,
@@ -106,8 +123,146 @@ pub struct RecordData {
     pub data: Data,
 }
 
-pub async fn download(window: Window) -> Result<PathBuf> {
-    download_database(window.clone()).await.and_then(unzip)
+impl From<RecordData> for source::Resource {
+    fn from(record: RecordData) -> Self {
+        let info = record.data.info;
+        source::Resource {
+            upstream_id: info.id as i64,
+            name: info.cnname,
+            original_name: info.enname,
+            alias_name: info.aliasname,
+            channel: info.channel,
+            channel_cn: info.channel_cn,
+            area: info.area,
+            show_type: info.show_type,
+            expire: info.expire,
+            views: info.views as i64,
+            year: info
+                .year
+                .iter()
+                .map(|year| year.to_string())
+                .collect::<Vec<String>>()
+                .join("/"),
+            seasons: record.data.list.into_iter().map(source::Season::from).collect(),
+        }
+    }
+}
+
+impl From<YYeTsSeason> for source::Season {
+    fn from(season: YYeTsSeason) -> Self {
+        let mut items = season.items;
+        source::Season {
+            season: season.season_num,
+            name: season.season_cn,
+            formats: season
+                .formats
+                .into_iter()
+                .map(|format| source::Format {
+                    episodes: items
+                        .remove(format.as_str())
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(source::Episode::from)
+                        .collect(),
+                    format,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<Item> for source::Episode {
+    fn from(item: Item) -> Self {
+        source::Episode {
+            upstream_id: item.itemid,
+            episode: item.episode,
+            name: item.name,
+            size: item.size,
+            dateline: item.dateline,
+            yyets_trans: item.yyets_trans as i64,
+            links: item
+                .files
+                .unwrap_or_default()
+                .into_iter()
+                .map(|file| source::Link {
+                    way: file.way,
+                    way_cn: file.way_cn,
+                    address: file.address,
+                    password: file.passwd,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Reads the `yyets` table of an extracted dump in id order.
+pub struct YYeTsSource {
+    connection: Connection,
+    last_id: i64,
+}
+
+impl YYeTsSource {
+    pub fn open(db: &Path) -> Result<Self> {
+        Ok(YYeTsSource {
+            connection: connect(db)?,
+            last_id: 0,
+        })
+    }
+}
+
+impl DataSource for YYeTsSource {
+    fn total(&self) -> Result<i32> {
+        let size = self
+            .connection
+            .query_row("select count(*) as c from yyets", [], |row| {
+                row.get::<&str, i32>("c")
+            })?;
+        Ok(size)
+    }
+
+    fn read(&mut self, size: usize) -> Result<Vec<RawRecord>> {
+        let mut statement = self
+            .connection
+            .prepare_cached("select id, data from yyets where id > ?1 order by id asc limit ?2")?;
+        let mut rows = statement.query(params![self.last_id, size as i64])?;
+        let mut batch = Vec::with_capacity(size);
+        while let Some(row) = rows.next()? {
+            let id = row.get::<&str, i64>("id")?;
+            batch.push((id, row.get::<&str, String>("data").map_err(anyhow::Error::from)));
+            self.last_id = id;
+        }
+        Ok(batch)
+    }
+
+    fn parser(&self) -> Parser {
+        |data| Ok(source::Resource::from(serde_json::from_str::<RecordData>(data)?))
+    }
+}
+
+/// Downloads and extracts the dump, returning its path and the mirror used.
+pub async fn download(reporter: &Reporter, token: &CancellationToken) -> Result<(PathBuf, String)> {
+    let (buf, source) = download_database(reporter, token).await?;
+    let db = unzip(buf)?;
+    token.check()?;
+    Ok((db, source))
+}
+
+/// Removes leftover dumps, except the one the initialization state still
+/// refers to, which a later run resumes from.
+pub fn clear_tmp_files() {
+    let cache_dir = match path::cache_dir() {
+        Some(dir) => dir,
+        None => return,
+    };
+    let dump = PathBuf::from(status::current().dump);
+    if let Ok(entries) = read_dir(cache_dir) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with(TMP_PREFIX) && name.ends_with(".db") && entry.path() != dump {
+                let _ = remove_file(entry.path());
+            }
+        }
+    }
 }
 
 fn tmp_path(file: &str) -> Result<PathBuf> {
@@ -125,8 +280,74 @@ fn tmp_file(path: &PathBuf) -> Result<File> {
     }
 }
 
-async fn download_database(window: Window) -> Result<Cursor<Vec<u8>>> {
-    let response = reqwest::get(DOWNLOAD_URL).await?;
+/// Extracts a dump archive that is already on disk.
+pub fn extract(archive: &Path) -> Result<PathBuf> {
+    unzip(File::open(archive)?)
+}
+
+async fn download_database(
+    reporter: &Reporter,
+    token: &CancellationToken,
+) -> Result<(Cursor<Vec<u8>>, String)> {
+    let mirrors = settings::load().mirrors;
+    let total = mirrors.len();
+    let mut errors = vec![];
+    for (index, mirror) in mirrors.iter().enumerate() {
+        token.check()?;
+        let mut attempt = MirrorAttempt {
+            url: mirror.url.to_string(),
+            index: index + 1,
+            total,
+            error: None,
+        };
+        emit_initialize_event(
+            reporter,
+            Response::ok(
+                format!("连接镜像 {}", mirror.url).as_str(),
+                ResponseData::new("mirror", attempt.clone()),
+            ),
+        )
+        .ok();
+        match download_from_mirror(reporter, token, mirror).await {
+            Ok(buf) => return Ok((buf, mirror.url.to_string())),
+            Err(e) => {
+                token.check()?;
+                errors.push(format!("{}: {}", mirror.url, e));
+                attempt.error = Some(e.to_string());
+                emit_initialize_event(
+                    reporter,
+                    Response::ok(
+                        format!("镜像 {} 下载失败：{}", mirror.url, e).as_str(),
+                        ResponseData::new("mirror", attempt),
+                    ),
+                )
+                .ok();
+            }
+        }
+    }
+    if errors.is_empty() {
+        return Err(anyhow::Error::from(YYeTsError::new(
+            "No download mirror configured",
+        )));
+    }
+    Err(anyhow::Error::from(YYeTsError::new(format!(
+        "Failed to download yyets database from every mirror: {}",
+        errors.join("; ")
+    ))))
+}
+
+async fn download_from_mirror(
+    reporter: &Reporter,
+    token: &CancellationToken,
+    mirror: &Mirror,
+) -> Result<Cursor<Vec<u8>>> {
+    let timeout = Duration::from_secs(mirror.timeout);
+    let timed_out = || YYeTsError::new(format!("Timed out after {} seconds", mirror.timeout));
+    let client = http::builder()?.connect_timeout(timeout).build()?;
+    let response = async_std::future::timeout(timeout, client.get(mirror.url.as_str()).send())
+        .await
+        .map_err(|_| timed_out())??
+        .error_for_status()?;
     let total_size = response
         .content_length()
         .ok_or(YYeTsError::new("Failed to download yyets database"))?;
@@ -134,25 +355,24 @@ async fn download_database(window: Window) -> Result<Cursor<Vec<u8>>> {
     let mut stream = response.bytes_stream();
     let mut buf = Cursor::new(Vec::new());
     let mut downloaded: u64 = 0;
-    while let Some(item) = stream.next().await {
+    let mut throttle = ProgressThrottle::new("downloading");
+    while let Some(item) = async_std::future::timeout(timeout, stream.next())
+        .await
+        .map_err(|_| timed_out())?
+    {
+        token.check()?;
         let chunk = item?;
         buf.write_all(&chunk)?;
         downloaded += chunk.len() as u64;
-        emit_initialize_event(
-            &window,
-            Response::ok(
-                "下载人人影视数据库",
-                ResponseData::new("downloading", (downloaded, total_size)),
-            ),
-        )
-        .ok();
+        if let Some(data) = throttle.poll(downloaded, total_size) {
+            emit_initialize_event(reporter, Response::ok("下载人人影视数据库", data)).ok();
+        }
     }
     Ok(buf)
 }
 
 fn unzip(reader: impl Read + Seek) -> Result<PathBuf> {
-    let mut random_db = random_string()?;
-    random_db.push_str(".db");
+    let random_db = format!("{}{}.db", TMP_PREFIX, random_string()?);
     let mut archive = ZipArchive::new(reader)?;
     let mut zip_file = archive.by_name("yyets_sqlite.db")?;
     let db = tmp_path(&random_db)?;
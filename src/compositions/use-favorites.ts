@@ -8,6 +8,8 @@ export interface Resource {
     original_name: string;
     alias_name: string;
     pic: string;
+    pic_list: string;
+    pic_detail: string;
 }
 
 export default () => {
@@ -18,6 +20,8 @@ export default () => {
     request<Resource[]>('favorites')
         .then((response) => Promise.all(response.map(async item => {
             item.pic = await convertSrc(item.pic);
+            item.pic_list = await convertSrc(item.pic_list);
+            item.pic_detail = await convertSrc(item.pic_detail);
             return item;
         })))
         .then(data => {
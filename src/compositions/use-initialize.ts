@@ -2,48 +2,101 @@ import { useDialog } from 'naive-ui';
 import { Ref, ref } from 'vue';
 import { event } from '@tauri-apps/api';
 import { Response } from '@/libs/response';
+import request from '@/libs/request';
 import { useStore } from '@/store';
 
-type Step = 'downloading' | 'importing' | 'finish';
+type Step = 'mirror' | 'downloading' | 'importing' | 'finish' | 'cancelled';
+
+interface InitializeState {
+    status: 'not_started' | 'downloading' | 'downloaded' | 'importing' | 'complete' | 'failed',
+    source: string,
+    dump: string,
+    error: string,
+    started_at: number,
+    updated_at: number,
+}
 
 interface ResponseData {
     step: Step,
-    data: any
+    data: any,
+    elapsed: number,
+    rate: number,
+    eta: number | null,
 }
 
-export default (): [Ref<number>, Ref<number>, Ref<string>, Ref<Step>] => {
+const formatSeconds = (seconds: number): string => {
+    const total = Math.ceil(seconds);
+    const minutes = Math.floor(total / 60);
+    return minutes > 0 ? `${minutes} 分 ${total % 60} 秒` : `${total} 秒`;
+};
+
+export default (): [Ref<number>, Ref<number>, Ref<string>, Ref<Step>, () => Promise<void>, Ref<string>] => {
     const percentage = ref(0);
     const importPercentage = ref(0);
     const message = ref('');
+    const detail = ref('');
     const dialog = useDialog();
     const store = useStore();
     const step = ref<Step>('downloading');
 
+    const retry = async () => {
+        await request.request('retry_initialize');
+    };
+
+    const fail = (content: string) => {
+        dialog.error({
+            content,
+            maskClosable: false,
+            positiveText: '重试',
+            negativeText: '关闭',
+            closable: false,
+            closeOnEsc: false,
+            showIcon: false,
+            onPositiveClick: () => {
+                retry();
+            },
+            onNegativeClick: () => {
+                store.dispatch('window/close');
+            }
+        });
+    };
+
+    request.request<Response<InitializeState>>('initialize_state')
+        .then(response => {
+            if (response.data.status === 'failed') {
+                fail(`上次初始化失败：${response.data.error}`);
+            }
+        });
+
     event.listen<Response<ResponseData>>('rubick_initialize', (event) => {
         if (event.payload.code !== 0) {
-            dialog.error({
-                content: event.payload.message,
-                maskClosable: false,
-                positiveText: '关闭',
-                closable: false,
-                closeOnEsc: false,
-                showIcon: false,
-                onPositiveClick: () => {
-                    store.dispatch('window/close');
-                }
-            });
+            fail(event.payload.message);
             return;
         }
         message.value = event.payload.message;
         step.value = event.payload.data.step;
+        if (event.payload.data.step === 'cancelled') {
+            store.dispatch('window/close');
+            return;
+        }
+        const { rate, eta } = event.payload.data;
+        const remaining = eta === null ? '' : `，剩余 ${formatSeconds(eta)}`;
         if (event.payload.data.step === 'downloading') {
             const [processed, total] = event.payload.data.data as [number, number];
             percentage.value = Math.round(processed / total * 100);
+            detail.value = `${(rate / 1024 / 1024).toFixed(2)} MB/s${remaining}`;
         } else if (event.payload.data.step === 'importing') {
             const [processed, total] = event.payload.data.data as [number, number];
             importPercentage.value = Math.round(processed / total * 100);
+            detail.value = `${Math.round(rate)} 条/秒${remaining}`;
+        } else {
+            detail.value = '';
         }
     });
 
-    return [percentage, importPercentage, message, step];
+    const cancel = async () => {
+        await request.request('cancel_initialize');
+    };
+
+    return [percentage, importPercentage, message, step, cancel, detail];
 }
@@ -1,13 +1,27 @@
+import { event } from '@tauri-apps/api';
 import convertSrc from '@/libs/convert-src';
-import { ref } from 'vue';
+import { Response } from '@/libs/response';
+import { onUnmounted, ref } from 'vue';
 import useRequest from './use-request';
 
+export interface ParsedLink {
+    kind: 'magnet' | 'ed2k' | 'cloud' | 'http' | '',
+    name: string,
+    size: number,
+    hash: string,
+    trackers: string[],
+    drive: string,
+    password: string,
+}
+
 export interface SeriesFile {
     id: number,
     series_id: number,
     address: string,
     password: string,
     way: string,
+    way_code: string,
+    link: ParsedLink,
 }
 
 export interface Series {
@@ -16,6 +30,9 @@ export interface Series {
     episode: number,
     name: string,
     size: string,
+    upstream_id: string,
+    dateline: string,
+    yyets_trans: number,
     files: SeriesFile[],
 }
 
@@ -40,6 +57,8 @@ export interface Resource {
     original_name: string,
     alias_name: string,
     pic: string,
+    pic_list: string,
+    pic_detail: string,
     directors: string,
     writers: string,
     actors: string,
@@ -47,23 +66,54 @@ export interface Resource {
     released_at: string,
     summary: string,
     rating: number,
+    votes: number,
+    rating_distribution: number[],
+    imdb_id: string,
+    episodes: number,
+    runtime: number,
+    countries: string,
+    languages: string,
     seasons: Season[],
     channel: string,
+    channel_cn: string,
     area: string,
-    favorite?: boolean
+    favorite?: boolean,
+    upstream_id: number,
+    show_type: string,
+    expire: string,
+    views: number,
+    year: string,
 }
 
 export default (id: number) => {
     const resource = ref<Resource | null>(null);
     const { loading, request } = useRequest();
 
+    const convert = async (response: Resource) => {
+        response.pic = await convertSrc(response.pic);
+        response.pic_list = await convertSrc(response.pic_list);
+        response.pic_detail = await convertSrc(response.pic_detail);
+        return response;
+    };
+
+    // Metadata missing from the database is fetched in the background and
+    // arrives here, whether the lookup succeeded or not.
+    const unlisten = event.listen<Response<Resource | null>>('rubick_resource', async (event) => {
+        const enriched = event.payload.data;
+        if (enriched && enriched.id === id) {
+            resource.value = await convert(enriched);
+        }
+    });
+    onUnmounted(() => {
+        unlisten.then(fn => fn());
+    });
+
     request<Resource>('resource', { id })
-        .then(async response => {
-            response.pic = await convertSrc(response.pic);
-            return response;
-        })
+        .then(convert)
         .then(response => {
-            resource.value = response;
+            if (resource.value === null) {
+                resource.value = response;
+            }
         })
         .catch(() => { });
 